@@ -0,0 +1,123 @@
+use sdl2::rect::Point;
+use sdl2::render::Renderer;
+use sdl2::pixels::Color;
+use physics::Vec2;
+use super::{Camera, CameraDrawable};
+
+/// How close, in world pixels, the reticle must come to an
+/// interactable to snap to it, unless told otherwise.
+pub const DEFAULT_SNAP_DISTANCE: f64 = 32.0;
+
+const RETICLE_SIZE: i32 = 8;
+
+/// Returns the pixel of a `view` sized view shown at window pixel
+/// `x`, `y`, when the view is scaled to fit a `window` sized window
+/// without stretching and centred between bars. Pixels over the
+/// bars are clamped to the nearest edge of the view.
+pub fn window_to_view(x: i32, y: i32, window: (u32, u32), view: (f64, f64)) -> (i32, i32) {
+    let (ww, wh) = (window.0 as f64, window.1 as f64);
+    let (vw, vh) = view;
+    if ww == 0.0 || wh == 0.0 || vw == 0.0 || vh == 0.0 {
+        return (x, y);
+    }
+    let scale = (ww / vw).min(wh / vh);
+    let (bar_x, bar_y) = ((ww - vw*scale) / 2.0, (wh - vh*scale) / 2.0);
+    let vx = ((x as f64 - bar_x) / scale).max(0.0).min(vw - 1.0);
+    let vy = ((y as f64 - bar_y) / scale).max(0.0).min(vh - 1.0);
+    (vx as i32, vy as i32)
+}
+
+/// A crosshair that follows the mouse, or anything else that
+/// moves it, for aiming shots and grappling hooks.
+///
+/// The reticle keeps its place on screen as the camera moves, and
+/// snaps to the nearest interactable, such as a grapple anchor,
+/// within `snap_distance` of where it points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reticle {
+    /// Where the reticle is on screen, in view pixels.
+    pub screen: (i32, i32),
+    /// Where the reticle points in the world, after any snapping.
+    pub pos: Vec2,
+    /// Whether `pos` is snapped to an interactable.
+    pub snapped: bool,
+    /// `None` turns snapping off.
+    pub snap_distance: Option<f64>,
+    pub visible: bool,
+}
+
+impl Reticle {
+    pub fn new() -> Self {
+        Reticle {
+            screen: (0, 0),
+            pos: Vec2::zero(),
+            snapped: false,
+            snap_distance: Some(DEFAULT_SNAP_DISTANCE),
+            visible: true,
+        }
+    }
+
+    /// Put the reticle under window pixel `x`, `y`, e.g. the mouse,
+    /// for a `window` sized window showing `c`'s view scaled to fit.
+    pub fn point_at(&mut self, x: i32, y: i32, window: (u32, u32), c: &Camera) {
+        self.screen = window_to_view(x, y, window, (c.width, c.height));
+    }
+
+    /// Move the reticle by `dx`, `dy` view pixels, e.g. from a
+    /// gamepad stick, keeping it within `c`'s view.
+    pub fn move_by(&mut self, dx: i32, dy: i32, c: &Camera) {
+        let (x, y) = self.screen;
+        self.screen = ((x + dx).max(0).min(c.width as i32 - 1),
+                       (y + dy).max(0).min(c.height as i32 - 1));
+    }
+
+    /// Work out where the reticle points in the world through
+    /// `c`, snapping to the nearest of `targets` in range.
+    pub fn update(&mut self, c: &Camera, targets: &[Vec2]) {
+        let (x, y) = self.screen;
+        let p = c.screen_to_world(x, y);
+        let nearest = self.snap_distance.and_then(|max| {
+            targets.iter()
+                .map(|&t| (t, (t - p).length()))
+                .filter(|&(_, dist)| dist <= max)
+                .fold(None, |best: Option<(Vec2, f64)>, (t, dist)| match best {
+                    Some((_, best_dist)) if best_dist <= dist => best,
+                    _ => Some((t, dist)),
+                })
+        });
+        match nearest {
+            Some((t, _)) => {
+                self.pos = t;
+                self.snapped = true;
+            },
+            None => {
+                self.pos = p;
+                self.snapped = false;
+            },
+        }
+    }
+
+    /// Returns the unit vector from `from` towards where the
+    /// reticle points, or the zero vector if it points at `from`.
+    pub fn aim_from(&self, from: Vec2) -> Vec2 {
+        (self.pos - from).normalize()
+    }
+}
+
+impl CameraDrawable for Reticle {
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        if !self.visible {
+            return;
+        }
+        // unsnapped, stay under the mouse while the camera moves
+        // between ticks
+        let (x, y) = if self.snapped { c.world_to_screen(self.pos) } else { self.screen };
+        let draw_col = r.draw_color();
+        r.set_draw_color(if self.snapped { Color::RGB(255, 200, 0) } else { Color::RGB(255, 255, 255) });
+        r.draw_line(Point::new(x - RETICLE_SIZE, y), Point::new(x - 2, y));
+        r.draw_line(Point::new(x + 2, y), Point::new(x + RETICLE_SIZE, y));
+        r.draw_line(Point::new(x, y - RETICLE_SIZE), Point::new(x, y - 2));
+        r.draw_line(Point::new(x, y + 2), Point::new(x, y + RETICLE_SIZE));
+        r.set_draw_color(draw_col);
+    }
+}
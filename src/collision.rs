@@ -0,0 +1,213 @@
+/// Side length, in tiles, of the square chunks a `CollisionMask`
+/// tracks solid tile counts for. Each chunk row is one `u64`.
+pub const CHUNK_SIZE: u32 = 64;
+
+/// A packed bitmask of solid tiles. Each row of the map is stored
+/// as `u64` words, one bit per tile, and the number of solid tiles
+/// in every `CHUNK_SIZE` x `CHUNK_SIZE` chunk is kept so that empty
+/// regions can be skipped without looking at their bits.
+#[derive(Clone, Debug)]
+pub struct CollisionMask {
+    pub width: u32,
+    pub height: u32,
+    /// Number of `u64` words per row.
+    words: u32,
+    bits: Vec<u64>,
+    /// Solid tile count per chunk, row-major.
+    counts: Vec<u32>,
+}
+
+impl CollisionMask {
+    /// Create a new `CollisionMask` with no solid tiles.
+    pub fn new(width: u32, height: u32) -> Self {
+        let words = (width + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let chunk_rows = (height + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        CollisionMask {
+            width: width,
+            height: height,
+            words: words,
+            bits: vec![0; (words * height) as usize],
+            counts: vec![0; (words * chunk_rows) as usize],
+        }
+    }
+
+    fn word_index(&self, col: u32, row: u32) -> usize {
+        (row * self.words + col / CHUNK_SIZE) as usize
+    }
+
+    fn chunk_index(&self, col: u32, row: u32) -> usize {
+        ((row / CHUNK_SIZE) * self.words + col / CHUNK_SIZE) as usize
+    }
+
+    pub fn set(&mut self, col: u32, row: u32, solid: bool) {
+        if col >= self.width || row >= self.height {
+            return;
+        }
+        let (w, c) = (self.word_index(col, row), self.chunk_index(col, row));
+        let bit = 1u64 << (col % CHUNK_SIZE);
+        let was_solid = self.bits[w] & bit != 0;
+        if solid && !was_solid {
+            self.bits[w] |= bit;
+            self.counts[c] += 1;
+        } else if !solid && was_solid {
+            self.bits[w] &= !bit;
+            self.counts[c] -= 1;
+        }
+    }
+
+    /// Whether the tile at `col`, `row` is solid. Tiles outside
+    /// the mask are never solid.
+    pub fn is_solid(&self, col: u32, row: u32) -> bool {
+        if col >= self.width || row >= self.height {
+            return false;
+        }
+        self.bits[self.word_index(col, row)] & (1u64 << (col % CHUNK_SIZE)) != 0
+    }
+
+    /// Whether the chunk containing `col`, `row` has no solid tiles.
+    pub fn chunk_is_empty(&self, col: u32, row: u32) -> bool {
+        if col >= self.width || row >= self.height {
+            return true;
+        }
+        self.counts[self.chunk_index(col, row)] == 0
+    }
+
+    /// Whether any tile from `first_col` to `last_col` (inclusive)
+    /// of `row` is solid.
+    pub fn row_span_solid(&self, row: u32, first_col: u32, last_col: u32) -> bool {
+        if row >= self.height || first_col > last_col || first_col >= self.width {
+            return false;
+        }
+        let last_col = if last_col >= self.width { self.width - 1 } else { last_col };
+
+        let (first_word, last_word) = (first_col / CHUNK_SIZE, last_col / CHUNK_SIZE);
+        for word in first_word..last_word + 1 {
+            let mut mask = !0u64;
+            if word == first_word {
+                mask &= !0u64 << (first_col % CHUNK_SIZE);
+            }
+            if word == last_word {
+                mask &= !0u64 >> (CHUNK_SIZE - 1 - last_col % CHUNK_SIZE);
+            }
+            if self.bits[(row * self.words + word) as usize] & mask != 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether any tile from `first_row` to `last_row` (inclusive)
+    /// of `col` is solid.
+    pub fn col_span_solid(&self, col: u32, first_row: u32, last_row: u32) -> bool {
+        if col >= self.width || first_row > last_row || first_row >= self.height {
+            return false;
+        }
+        let last_row = if last_row >= self.height { self.height - 1 } else { last_row };
+        let bit = 1u64 << (col % CHUNK_SIZE);
+        (first_row..last_row + 1).any(|row| self.bits[self.word_index(col, row)] & bit != 0)
+    }
+
+    /// Whether any tile in the inclusive tile rectangle is solid.
+    /// Empty chunks are skipped entirely.
+    pub fn area_solid(&self, first_col: u32, first_row: u32, last_col: u32, last_row: u32) -> bool {
+        if first_col > last_col || first_row > last_row {
+            return false;
+        }
+        let mut row = first_row;
+        while row <= last_row && row < self.height {
+            // rows up to the end of this chunk row, or the area
+            let chunk_end = (row / CHUNK_SIZE + 1) * CHUNK_SIZE - 1;
+            let end = if chunk_end < last_row { chunk_end } else { last_row };
+            let mut col = first_col;
+            while col <= last_col && col < self.width {
+                let col_end = (col / CHUNK_SIZE + 1) * CHUNK_SIZE - 1;
+                let col_end = if col_end < last_col { col_end } else { last_col };
+                if !self.chunk_is_empty(col, row) {
+                    for r in row..end + 1 {
+                        if self.row_span_solid(r, col, col_end) {
+                            return true;
+                        }
+                    }
+                }
+                col = col_end + 1;
+            }
+            row = end + 1;
+        }
+        false
+    }
+}
+
+/// Flags of a `CollisionGrid` cell.
+pub const CELL_SOLID: u8 = 1 << 0;
+/// Solid only from above: can be stood on and jumped up through.
+pub const CELL_ONE_WAY: u8 = 1 << 1;
+/// Harmful to enter, whether or not it's solid.
+pub const CELL_HAZARD: u8 = 1 << 2;
+/// A ladder, vine or other tile the player can climb.
+pub const CELL_CLIMBABLE: u8 = 1 << 3;
+
+/// A map's tiles reduced to a byte of flags each, row by row, for
+/// pathfinding and other AI queries that don't care how tiles look.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CollisionGrid {
+    pub width: u32,
+    pub height: u32,
+    pub cells: Vec<u8>,
+}
+
+impl CollisionGrid {
+    /// Create a new `CollisionGrid` with every cell empty.
+    pub fn new(width: u32, height: u32) -> Self {
+        CollisionGrid {
+            width: width,
+            height: height,
+            cells: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Returns the flags of the cell at `col`, `row`. Cells
+    /// outside the grid are solid, so paths stay inside it.
+    pub fn get(&self, col: u32, row: u32) -> u8 {
+        if col >= self.width || row >= self.height {
+            return CELL_SOLID;
+        }
+        self.cells[(row * self.width + col) as usize]
+    }
+
+    /// Add `flags` to the cell at `col`, `row`.
+    pub fn insert(&mut self, col: u32, row: u32, flags: u8) {
+        if col < self.width && row < self.height {
+            self.cells[(row * self.width + col) as usize] |= flags;
+        }
+    }
+
+    pub fn is_solid(&self, col: u32, row: u32) -> bool {
+        self.get(col, row) & CELL_SOLID != 0
+    }
+
+    pub fn is_one_way(&self, col: u32, row: u32) -> bool {
+        self.get(col, row) & CELL_ONE_WAY != 0
+    }
+
+    pub fn is_hazard(&self, col: u32, row: u32) -> bool {
+        self.get(col, row) & CELL_HAZARD != 0
+    }
+
+    pub fn is_climbable(&self, col: u32, row: u32) -> bool {
+        self.get(col, row) & CELL_CLIMBABLE != 0
+    }
+
+    /// Whether the cell at `col`, `row` can be moved through
+    /// safely: it's neither solid nor harmful. One-way cells
+    /// can be moved through.
+    pub fn is_walkable(&self, col: u32, row: u32) -> bool {
+        let flags = self.get(col, row);
+        flags & CELL_HAZARD == 0 && (flags & CELL_SOLID == 0 || flags & CELL_ONE_WAY != 0)
+    }
+
+    /// Whether something can stand in the cell at `col`, `row`:
+    /// it's walkable and the cell below is solid or one-way.
+    pub fn is_standable(&self, col: u32, row: u32) -> bool {
+        self.is_walkable(col, row) && self.get(col, row + 1) & (CELL_SOLID | CELL_ONE_WAY) != 0
+    }
+}
@@ -0,0 +1,76 @@
+// Constructors with the signatures they had before positions became
+// `Vec2`s, rects `physics::Rect`s and maps owned by `Game`, forwarding
+// to the current ones so existing games keep compiling while they
+// move over. Everything here is deprecated and will be removed.
+#![allow(deprecated)]
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use sdl2::render::Texture;
+use sdl2::rect::Rect;
+use super::{Camera, Direction, Game, Player, Vec2};
+use map;
+use physics;
+
+/// A point in whole pixels, as positions used to be.
+#[deprecated(note = "use `Vec2`")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl Point {
+    /// Helper method to save typing out
+    /// the origin Point struct.
+    pub fn origin() -> Self {
+        Point{x: 0, y: 0}
+    }
+}
+
+impl From<Point> for Vec2 {
+    fn from(p: Point) -> Self {
+        Vec2::new(p.x as f64, p.y as f64)
+    }
+}
+
+/// Converts an SDL rect to the `physics::Rect` collision
+/// rects are now.
+#[deprecated(note = "use `physics::Rect::new`")]
+pub fn rect(r: Rect) -> physics::Rect {
+    physics::Rect::new(r.x(), r.y(), r.width(), r.height())
+}
+
+#[deprecated(note = "use `Camera::new`, which takes a `Vec2`, `f64` sizes and a `physics::Rect`")]
+pub fn camera(p: Point, w: i64, h: i64, cr: Rect) -> Camera {
+    Camera::new(p.into(), w as f64, h as f64, rect(cr))
+}
+
+#[deprecated(note = "use `Player::new`, which takes `Vec2`s and a `physics::Rect`")]
+pub fn player(p: Point,
+              cr: Rect,
+              t: Rc<RefCell<Texture>>,
+              dr: Option<Rect>,
+              d: Direction,
+              dtp: HashMap<Direction, u8>,
+              dtal: HashMap<Direction, u8>,
+              dtf: HashMap<Direction, u8>,
+              dto: HashMap<Direction, Point>,
+              reverse: bool) -> Player {
+    let dto = dto.into_iter().map(|(d, o)| (d, o.into())).collect();
+    Player::new(p.into(), rect(cr), t, dr, d, dtp, dtal, dtf, dto, reverse)
+}
+
+/// `Game` owns its map now, so this plays a copy of `current_map`;
+/// later changes to the original aren't seen by the game.
+#[deprecated(note = "use `Game::new`, which takes the map by value")]
+pub fn game(db: bool, current_map: Option<&mut map::Map>, cam: Camera, p: Player) -> Game {
+    Game::new(db, current_map.map(|m| m.clone()), cam, p)
+}
+
+/// Like `game`, this makes a copy of `map` the current map.
+#[deprecated(note = "use `Game::set_map`, which takes the map by value")]
+pub fn set_map(game: &mut Game, map: &mut map::Map) {
+    game.set_map(map.clone());
+}
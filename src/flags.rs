@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+/// The value of a narrative flag.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum FlagValue {
+    Bool(bool),
+    Counter(i64),
+}
+
+/// Global narrative state: booleans and counters set by triggers and
+/// dialog, and checked by spawn conditions and dialog branches. Flags
+/// that were never set are false, and counters that were never set
+/// are 0. A counter is true if it isn't 0.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Flags(pub HashMap<String, FlagValue>);
+
+/// Comparison operators understood by `Flags::check`, longest first
+/// so that `>=` isn't read as `>`.
+const OPERATORS: [&'static str; 6] = [">=", "<=", "==", "!=", ">", "<"];
+
+impl Flags {
+    pub fn new() -> Self {
+        Flags(HashMap::new())
+    }
+
+    pub fn get(&self, name: &str) -> bool {
+        match self.0.get(name) {
+            Some(&FlagValue::Bool(b)) => b,
+            Some(&FlagValue::Counter(n)) => n != 0,
+            None => false,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: bool) {
+        self.0.insert(name.to_string(), FlagValue::Bool(value));
+    }
+
+    /// Returns the counter `name`. A flag that's set counts as 1.
+    pub fn counter(&self, name: &str) -> i64 {
+        match self.0.get(name) {
+            Some(&FlagValue::Counter(n)) => n,
+            Some(&FlagValue::Bool(b)) => b as i64,
+            None => 0,
+        }
+    }
+
+    pub fn set_counter(&mut self, name: &str, value: i64) {
+        self.0.insert(name.to_string(), FlagValue::Counter(value));
+    }
+
+    /// Add `n` to the counter `name`, returning its new value.
+    pub fn add(&mut self, name: &str, n: i64) -> i64 {
+        let value = self.counter(name) + n;
+        self.set_counter(name, value);
+        value
+    }
+
+    /// Forget `name`, so it reads as false and 0 again.
+    pub fn clear(&mut self, name: &str) {
+        self.0.remove(name);
+    }
+
+    /// Whether `condition` holds. Conditions are a flag name, a flag
+    /// name preceded by `!`, or a counter compared with a number using
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`, e.g. `keys >= 3`. Several
+    /// conditions can be joined with `&&`. An empty condition holds.
+    pub fn check(&self, condition: &str) -> bool {
+        condition.split("&&").all(|c| self.check_one(c.trim()))
+    }
+
+    fn check_one(&self, condition: &str) -> bool {
+        if condition.is_empty() {
+            return true;
+        }
+        if condition.starts_with('!') && !condition.starts_with("!=") {
+            return !self.get(condition[1..].trim());
+        }
+        for op in OPERATORS.iter() {
+            if let Some(i) = condition.find(op) {
+                let name = condition[..i].trim();
+                let value = match condition[i + op.len()..].trim().parse::<i64>() {
+                    Ok(v) => v,
+                    Err(_) => return false,
+                };
+                let n = self.counter(name);
+                return match *op {
+                    ">=" => n >= value,
+                    "<=" => n <= value,
+                    "==" => n == value,
+                    "!=" => n != value,
+                    ">" => n > value,
+                    _ => n < value,
+                };
+            }
+        }
+        self.get(condition)
+    }
+}
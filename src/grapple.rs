@@ -0,0 +1,192 @@
+use tiled;
+use spawn;
+use physics::{self, Vec2};
+
+/// Object type of grappling hook anchors in Tiled object layers.
+pub const GRAPPLE_ANCHOR_TYPE: &'static str = "grapple_anchor";
+
+/// Object type of zip lines in Tiled object layers.
+pub const ZIP_LINE_TYPE: &'static str = "zip_line";
+
+/// How far, in pixels, a grappling hook reaches an anchor from,
+/// unless the anchor's `range` property says otherwise.
+pub const DEFAULT_GRAPPLE_RANGE: f64 = 250.0;
+
+/// How close, in pixels, the player's hands must be to a
+/// zip line to grab it.
+pub const GRAB_DISTANCE: f64 = 12.0;
+
+/// Top speed along a zip line, in pixels per tick, unless the
+/// line's `max_speed` property says otherwise.
+pub const DEFAULT_ZIP_SPEED: f64 = 20.0;
+
+/// Fraction of gravity along a zip line's slope that speeds
+/// up a rider each tick.
+const SLOPE_ACCELERATION: f64 = 0.2;
+const ZIP_FRICTION: f64 = 0.95;
+
+/// A point for the grappling hook to latch onto.
+///
+/// Anchors are `grapple_anchor` objects, at the object's centre
+/// (or the point, for a point object). Their `range` property is
+/// how close, in pixels, the hook must be fired from.
+#[derive(Clone, Debug)]
+pub struct GrappleAnchor {
+    pub name: String,
+    /// Where the hook latches on, in world pixels.
+    pub pos: Vec2,
+    pub range: f64,
+}
+
+impl GrappleAnchor {
+    /// Build a `GrappleAnchor` from `o`, which is in a layer
+    /// offset by `offset` pixels.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Self {
+        GrappleAnchor {
+            name: o.name.clone(),
+            pos: Vec2::new(o.x + o.width/2.0 + offset.0, o.y + o.height/2.0 + offset.1),
+            range: o.properties.get_float("range").unwrap_or(DEFAULT_GRAPPLE_RANGE),
+        }
+    }
+
+    /// Every grapple anchor in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, GRAPPLE_ANCHOR_TYPE, |o, offset| Some(GrappleAnchor::from_object(o, offset)))
+    }
+}
+
+/// Returns the index in `anchors` of the nearest anchor
+/// within range of `from`, if there is one.
+pub fn anchor_in_reach(anchors: &[GrappleAnchor], from: Vec2) -> Option<usize> {
+    let mut best = None;
+    let mut best_dist = ::std::f64::MAX;
+    for (i, a) in anchors.iter().enumerate() {
+        let dist = (a.pos - from).length();
+        if dist <= a.range && dist < best_dist {
+            best = Some(i);
+            best_dist = dist;
+        }
+    }
+    best
+}
+
+/// A straight cable the player can hang from and slide along.
+///
+/// Zip lines are `zip_line` polyline objects, running from the
+/// polyline's first point to its last; points in between are
+/// ignored. Their `max_speed` property is the top speed along the
+/// line, in pixels per tick.
+#[derive(Clone, Debug)]
+pub struct ZipLine {
+    pub name: String,
+    /// The ends of the line, in world pixels.
+    pub start: Vec2,
+    pub end: Vec2,
+    pub max_speed: f64,
+}
+
+impl ZipLine {
+    /// Build a `ZipLine` from `o`, which is in a layer offset by
+    /// `offset` pixels. `None` if it isn't a polyline of non-zero
+    /// length.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Option<Self> {
+        let points = match o.shape {
+            tiled::Shape::Polyline(ref points) if points.len() >= 2 => points,
+            _ => return None,
+        };
+        let origin = Vec2::new(o.x + offset.0, o.y + offset.1);
+        let (first, last) = (&points[0], &points[points.len() - 1]);
+        let line = ZipLine {
+            name: o.name.clone(),
+            start: origin + Vec2::new(first.x, first.y),
+            end: origin + Vec2::new(last.x, last.y),
+            max_speed: o.properties.get_float("max_speed").unwrap_or(DEFAULT_ZIP_SPEED),
+        };
+        if line.length() == 0.0 {
+            return None;
+        }
+        Some(line)
+    }
+
+    /// Every zip line in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, ZIP_LINE_TYPE, ZipLine::from_object)
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.end - self.start).length()
+    }
+
+    /// The unit vector from `start` to `end`.
+    pub fn direction(&self) -> Vec2 {
+        (self.end - self.start).normalize()
+    }
+
+    /// Returns the point a fraction `t` of the way along the line.
+    pub fn point_at(&self, t: f64) -> Vec2 {
+        self.start.lerp(&self.end, t)
+    }
+
+    /// Returns how far along the line, as a fraction, the point
+    /// nearest `p` is, and how far `p` is from it.
+    pub fn closest(&self, p: Vec2) -> (f64, f64) {
+        let d = self.end - self.start;
+        let t = ((p - self.start).dot(&d) / d.dot(&d)).max(0.0).min(1.0);
+        (t, (p - self.point_at(t)).length())
+    }
+}
+
+/// Returns the index in `lines` of the nearest zip line within
+/// grabbing distance of `hands`, and how far along it they are.
+pub fn zip_line_in_reach(lines: &[ZipLine], hands: Vec2) -> Option<(usize, f64)> {
+    let mut best = None;
+    let mut best_dist = GRAB_DISTANCE;
+    for (i, l) in lines.iter().enumerate() {
+        let (t, dist) = l.closest(hands);
+        if dist <= best_dist {
+            best = Some((i, t));
+            best_dist = dist;
+        }
+    }
+    best
+}
+
+/// The player's ride along a zip line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZipRide {
+    /// Index of the line in the map's `zip_lines`.
+    pub line: usize,
+    /// How far along the line the player is, as a fraction.
+    pub t: f64,
+    /// Speed towards the line's `end`, in pixels per tick.
+    /// Negative towards its `start`.
+    pub speed: f64,
+}
+
+impl ZipRide {
+    /// Start riding `lines[line]` at `t`, keeping the part
+    /// of velocity `v` that's along the line.
+    pub fn new(lines: &[ZipLine], line: usize, t: f64, v: Vec2) -> Self {
+        ZipRide {
+            line: line,
+            t: t,
+            speed: v.dot(&lines[line].direction()),
+        }
+    }
+
+    /// Slide along `line` for one tick, speeding up downhill and
+    /// slowing uphill. Returns false once the rider has slid off
+    /// an end.
+    pub fn step(&mut self, line: &ZipLine) -> bool {
+        self.speed += physics::GRAVITY * SLOPE_ACCELERATION * line.direction().y;
+        self.speed *= ZIP_FRICTION;
+        self.speed = self.speed.max(-line.max_speed).min(line.max_speed);
+        self.t += self.speed / line.length();
+        self.t >= 0.0 && self.t <= 1.0
+    }
+
+    /// The rider's velocity, for when they let go.
+    pub fn velocity(&self, line: &ZipLine) -> Vec2 {
+        line.direction() * self.speed
+    }
+}
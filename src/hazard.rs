@@ -0,0 +1,107 @@
+use std::f64::consts::PI;
+use tiled;
+use spawn;
+use physics::{Vec2, Rect};
+
+/// Object type of swinging and rotating hazards in Tiled object layers.
+pub const HAZARD_TYPE: &'static str = "hazard";
+
+const DEFAULT_LENGTH: f64 = 96.0;
+const DEFAULT_THICKNESS: f64 = 16.0;
+const DEFAULT_PERIOD: u32 = 2000;
+const DEFAULT_AMPLITUDE: f64 = 60.0;
+
+/// How a hazard moves about its pivot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Motion {
+    /// Swing to either side of hanging straight down, like a
+    /// pendulum blade, by up to `amplitude` radians.
+    Swing { amplitude: f64 },
+    /// Turn all the way round, like a bar of spikes.
+    Spin { clockwise: bool },
+}
+
+/// A blade or bar that rotates about a pivot and kills the player
+/// on contact. Its collision shape is a capsule: a segment from the
+/// pivot to the tip, thickened by `radius`.
+///
+/// Hazards are `hazard` objects, pivoting about the object's centre
+/// (or the point, for a point object). Their properties are `motion`
+/// (`swing` or `spin`), `length` and `thickness` (pixels), `period`
+/// (milliseconds per swing or turn), `amplitude` (degrees, for
+/// swings), `clockwise` (for spins) and `phase`, the fraction of a
+/// period the hazard starts at, so neighbouring hazards can move
+/// out of step.
+#[derive(Clone, Debug)]
+pub struct Hazard {
+    pub name: String,
+    pub pivot: Vec2,
+    pub length: f64,
+    pub radius: f64,
+    pub motion: Motion,
+    pub period: u32,
+    pub phase: f64,
+    /// Current angle in radians, clockwise from hanging straight down.
+    pub angle: f64,
+}
+
+impl Hazard {
+    /// Build a `Hazard` from `o`, which is in a layer
+    /// offset by `offset` pixels.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Self {
+        let p = &o.properties;
+        let motion = match p.get_str("motion") {
+            Some("spin") => Motion::Spin { clockwise: p.get_bool("clockwise").unwrap_or(true) },
+            _ => Motion::Swing {
+                amplitude: p.get_float("amplitude").unwrap_or(DEFAULT_AMPLITUDE).to_radians(),
+            },
+        };
+        let mut hazard = Hazard {
+            name: o.name.clone(),
+            pivot: Vec2::new(o.x + o.width/2.0 + offset.0, o.y + o.height/2.0 + offset.1),
+            length: p.get_float("length").unwrap_or(DEFAULT_LENGTH),
+            radius: p.get_float("thickness").unwrap_or(DEFAULT_THICKNESS) / 2.0,
+            motion: motion,
+            period: p.get_int("period").map_or(DEFAULT_PERIOD, |t| t.max(1) as u32),
+            phase: p.get_float("phase").unwrap_or(0.0),
+            angle: 0.0,
+        };
+        hazard.update(0);
+        hazard
+    }
+
+    /// Every hazard in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, HAZARD_TYPE, |o, offset| Some(Hazard::from_object(o, offset)))
+    }
+
+    /// Move the hazard to where it is `time` milliseconds after
+    /// the map was loaded.
+    pub fn update(&mut self, time: u32) {
+        let turns = (time % self.period) as f64 / self.period as f64 + self.phase;
+        self.angle = match self.motion {
+            Motion::Swing { amplitude } => amplitude * (2.0 * PI * turns).sin(),
+            Motion::Spin { clockwise: true } => 2.0 * PI * turns,
+            Motion::Spin { clockwise: false } => -2.0 * PI * turns,
+        };
+    }
+
+    /// The far end of the hazard, in world pixels.
+    pub fn tip(&self) -> Vec2 {
+        self.pivot + Vec2::new(-self.angle.sin(), self.angle.cos()) * self.length
+    }
+
+    /// Whether the hazard touches `rect`. The capsule is checked as
+    /// a row of circles no further apart than their radius.
+    pub fn hits(&self, rect: &Rect) -> bool {
+        let (left, top) = (rect.x() as f64, rect.y() as f64);
+        let (right, bottom) = (left + rect.width() as f64, top + rect.height() as f64);
+        let tip = self.tip();
+        let steps = (self.length / self.radius.max(1.0)).ceil().max(1.0) as u32;
+        (0..steps + 1).any(|i| {
+            let c = self.pivot.lerp(&tip, i as f64 / steps as f64);
+            let nearest = Vec2::new(c.x.max(left).min(right), c.y.max(top).min(bottom));
+            (c - nearest).length() <= self.radius
+        })
+    }
+}
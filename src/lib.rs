@@ -1,20 +1,48 @@
 extern crate sdl2;
 extern crate sdl2_image;
-extern crate rustc_serialize;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate base64;
+extern crate flate2;
+extern crate zstd;
+extern crate xml;
+extern crate rand;
 
 use std::rc::Rc;
-use std::path::Path;
-use std::sync::mpsc::Receiver;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::mem;
+use std::fmt;
+use std::time::{Duration, Instant};
 use std::collections::HashMap;
 use sdl2::EventPump;
-use sdl2::render::{Renderer, Texture};
+use sdl2::render::{Renderer, Texture, BlendMode};
 use sdl2::rect::Rect;
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Scancode};
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use rand::{Rng, SeedableRng, XorShiftRng};
 
 pub mod tiled;
 pub mod map;
+pub mod collision;
+pub mod prefetch;
+pub mod postprocess;
+pub mod teleport;
+pub mod physics;
+pub mod flags;
+pub mod spawn;
+pub mod grapple;
+pub mod hazard;
+pub mod mapgen;
+pub mod survival;
+pub mod compat;
+pub mod aim;
+
+pub use physics::Vec2;
 
 #[macro_export]
 macro_rules! hashmap {
@@ -25,63 +53,6 @@ macro_rules! hashmap {
     });
 }
 
-/// Returns a timer that sends the unit every
-/// `ms` milliseconds.
-fn timer_periodic(ms: u32) -> Receiver<()> {
-    let (tx, rx) = std::sync::mpsc::channel();
-    std::thread::spawn(move || {
-        loop {
-            std::thread::sleep_ms(ms);
-            if tx.send(()).is_err() {
-                break;
-            }
-        }
-    });
-    rx
-}
-
-/// Contains x, y position components.
-pub struct Point {
-    pub x: i64,
-    pub y: i64,
-}
-
-impl Point {
-    /// Helper method to save typing out
-    /// the origin Point struct.
-    pub fn origin() -> Self {
-        Point{x: 0, y: 0}
-    }
-}
-
-/// Contains x, y velocity components.
-pub struct Velocity {
-    pub x: f64,
-    pub y: f64,
-}
-
-impl Velocity {
-    /// Helper method to save typing out
-    /// the zero Velocity struct.
-    pub fn zero() -> Self {
-        Velocity{x: 0.0, y: 0.0}
-    }
-}
-
-/// Contains x, y acceleration components.
-pub struct Acceleration {
-    pub x: f64,
-    pub y: f64,
-}
-
-impl Acceleration {
-    /// Helper method to save typing out
-    /// the zero Acceleration struct.
-    pub fn zero() -> Self {
-        Acceleration{x: 0.0, y: 0.0}
-    }
-}
-
 /// Enumeration of directions in a platformer.
 #[derive(Hash, Eq, PartialEq, Clone, Debug)]
 pub enum Direction {
@@ -93,47 +64,277 @@ pub enum Direction {
     Right,
     StillRight,
     Landed,
+    /// On a ladder or other climbable tile.
+    Climb,
 }
 
 /// Holds information pertaining to the game's camera.
 pub struct Camera {
-    pub pos: Point,
-    pub width: i64,
-    pub height: i64,
-    pub collision_rect: Rect,
+    pub pos: Vec2,
+    /// Position at the previous simulation tick.
+    pub prev_pos: Vec2,
+    pub width: f64,
+    pub height: f64,
+    pub collision_rect: physics::Rect,
+    /// Fraction of a simulation tick elapsed since the last one,
+    /// used to draw things between their previous and current
+    /// positions.
+    pub alpha: f64,
+    /// Degrees the view is turned clockwise about the centre of the
+    /// screen, for earthquakes, dizziness or rotating rooms. Only
+    /// drawing is rotated: collision and physics stay axis-aligned.
+    pub rotation: f64,
 }
 
 impl Camera {
     /// Create a new `Camera`
-    pub fn new(p: Point, w: i64, h: i64, cr: Rect) -> Self {
+    pub fn new(p: Vec2, w: f64, h: f64, cr: physics::Rect) -> Self {
         Camera {
             pos: p,
+            prev_pos: p,
             width: w,
             height: h,
             collision_rect: cr,
+            alpha: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    /// Returns a copy of the camera at its position `alpha`
+    /// of the way through the current tick.
+    pub fn interpolated(&self) -> Self {
+        Camera {
+            pos: self.prev_pos.lerp(&self.pos, self.alpha),
+            ..*self
         }
     }
+
+    fn centre(&self) -> Vec2 {
+        Vec2::new(self.width/2.0, self.height/2.0)
+    }
+
+    /// Returns where world position `p` is on screen, in pixels.
+    pub fn world_to_screen(&self, p: Vec2) -> (i32, i32) {
+        let centre = self.centre();
+        (centre + (p - self.pos - centre).rotate(self.rotation)).to_pixel()
+    }
+
+    /// Returns the world position at screen pixel `x`, `y`.
+    pub fn screen_to_world(&self, x: i32, y: i32) -> Vec2 {
+        let centre = self.centre();
+        self.pos + centre + (Vec2::from((x, y)) - centre).rotate(-self.rotation)
+    }
+
+    /// The part of the world the camera sees, in world pixels.
+    /// While the camera is rotated, this is the smallest rect
+    /// around everything on screen.
+    pub fn view_rect(&self) -> physics::Rect {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let w = self.width*cos.abs() + self.height*sin.abs();
+        let h = self.width*sin.abs() + self.height*cos.abs();
+        let (x, y) = (self.pos + self.centre() - Vec2::new(w/2.0, h/2.0)).to_pixel();
+        physics::Rect::new(x, y, w.ceil() as u32, h.ceil() as u32)
+    }
 }
 
 /// Building block struct that holds the basic
 /// data that all game entities need.
 pub struct Entity {
-    pub pos: Point,
-    pub collision_rect: Rect,
-    pub sprite_map: Rc<Texture>,
+    pub pos: Vec2,
+    /// Position at the previous simulation tick.
+    pub prev_pos: Vec2,
+    pub collision_rect: physics::Rect,
+    pub sprite_map: Rc<RefCell<Texture>>,
     pub draw_rect: Option<Rect>,
+    /// Extra sprite maps drawn over `sprite_map`, in order.
+    pub layers: Vec<SpriteLayer>,
+    pub outline: Option<Outline>,
+    /// White silhouette of the sprite maps the outline is drawn
+    /// from, and which of `layers` were visible when it was made.
+    outline_mask: Option<(Vec<bool>, Texture)>,
 }
 
 impl Entity {
     /// Create a new `Entity`.
-    fn new(p: Point, cr: Rect, t: Rc<Texture>, dr: Option<Rect>) -> Self {
+    fn new(p: Vec2, cr: physics::Rect, t: Rc<RefCell<Texture>>, dr: Option<Rect>) -> Self {
         Entity {
             pos: p,
+            prev_pos: p,
             collision_rect: cr,
             sprite_map: t,
             draw_rect: dr,
+            layers: Vec::new(),
+            outline: None,
+            outline_mask: None,
+        }
+    }
+
+    /// Returns the position to draw the entity at, a fraction
+    /// `alpha` of the way through the current tick.
+    pub fn draw_pos(&self, alpha: f64) -> Vec2 {
+        self.prev_pos.lerp(&self.pos, alpha)
+    }
+
+    /// Add a layer on top of the existing ones. The layer's sprite
+    /// map must use the same frame layout as `sprite_map`.
+    pub fn add_layer(&mut self, name: &str, t: Rc<RefCell<Texture>>) {
+        self.layers.push(SpriteLayer {
+            name: name.to_string(),
+            sprite_map: t,
+            visible: true,
+        });
+    }
+
+    pub fn remove_layer(&mut self, name: &str) {
+        self.layers.retain(|l| l.name != name);
+    }
+
+    pub fn set_layer_visible(&mut self, name: &str, visible: bool) {
+        for l in self.layers.iter_mut().filter(|l| l.name == name) {
+            l.visible = visible;
+        }
+    }
+
+    /// Copy `src` from the sprite map and each visible layer
+    /// to `dst`, modulated by `alpha`.
+    fn copy_layers(&self, r: &mut Renderer, src: Option<Rect>, dst: Option<Rect>, alpha: u8) {
+        let layers = self.layers.iter().filter(|l| l.visible).map(|l| &l.sprite_map);
+        for t in Some(&self.sprite_map).into_iter().chain(layers) {
+            let mut tx = t.borrow_mut();
+            if alpha != 255 {
+                tx.set_alpha_mod(alpha);
+            }
+            r.copy(&tx, src, dst);
+            if alpha != 255 {
+                tx.set_alpha_mod(255);
+            }
+        }
+    }
+
+    /// Set or clear the entity's outline. Call this again after
+    /// replacing a sprite map, so the outline follows it.
+    pub fn set_outline(&mut self, o: Option<Outline>) {
+        self.outline = o;
+        self.outline_mask = None;
+    }
+
+    /// Render the silhouette of `sprite_map` and the visible layers,
+    /// in white, for `copy_outline` to color. Left as `None` if the
+    /// renderer can't render to textures.
+    fn update_outline_mask(&mut self, r: &mut Renderer) {
+        let visible: Vec<bool> = self.layers.iter().map(|l| l.visible).collect();
+        if let Some((ref v, _)) = self.outline_mask {
+            if *v == visible {
+                return;
+            }
+        }
+        self.outline_mask = None;
+
+        let size = {
+            let q = self.sprite_map.borrow().query();
+            (q.width, q.height)
+        };
+        let mask = match r.create_texture_target(PixelFormatEnum::RGBA8888, size) {
+            Ok(mask) => mask,
+            Err(_) => return,
+        };
+        // drawing may already be redirected, e.g. by an EffectChain
+        let prev = match r.render_target().map(|mut rt| rt.set(mask)) {
+            Some(Ok(prev)) => prev,
+            _ => return,
+        };
+
+        let (draw_color, blend) = (r.draw_color(), r.blend_mode());
+        r.set_draw_color(Color::RGBA(0, 0, 0, 0));
+        r.clear();
+        let layers = self.layers.iter().filter(|l| l.visible).map(|l| &l.sprite_map);
+        for t in Some(&self.sprite_map).into_iter().chain(layers) {
+            let tx = t.borrow();
+            let q = tx.query();
+            r.copy(&tx, None, Some(Rect::new_unwrap(0, 0, q.width, q.height)));
+        }
+        // adding white saturates the color of every pixel,
+        // leaving its alpha alone
+        r.set_blend_mode(BlendMode::Add);
+        r.set_draw_color(Color::RGBA(255, 255, 255, 255));
+        r.fill_rect(Rect::new_unwrap(0, 0, size.0, size.1));
+        r.set_draw_color(draw_color);
+        r.set_blend_mode(blend);
+
+        let mask = match r.render_target() {
+            Some(mut rt) => match prev {
+                Some(prev) => rt.set(prev),
+                None => rt.reset(),
+            },
+            None => return,
+        };
+        if let Ok(Some(mut mask)) = mask {
+            mask.set_blend_mode(BlendMode::Blend);
+            self.outline_mask = Some((visible, mask));
         }
     }
+
+    /// Draw the outline, if any, of `src` copied to `dst`. Must
+    /// be called before the sprite itself is drawn.
+    fn copy_outline(&mut self, r: &mut Renderer, src: Option<Rect>, dst: Option<Rect>) {
+        let (o, dst) = match (self.outline, dst) {
+            (Some(o), Some(dst)) => (o, dst),
+            _ => return,
+        };
+        let (red, green, blue, alpha) = match o.color {
+            Color::RGB(red, green, blue) => (red, green, blue, 255),
+            Color::RGBA(red, green, blue, alpha) => (red, green, blue, alpha),
+        };
+        let offset = |dx: i32, dy: i32| {
+            Rect::new_unwrap(dst.x() + dx * o.thickness, dst.y() + dy * o.thickness, dst.width(), dst.height())
+        };
+
+        self.update_outline_mask(r);
+        if let Some((_, ref mut mask)) = self.outline_mask {
+            mask.set_color_mod(red, green, blue);
+            mask.set_alpha_mod(alpha);
+            for &(dx, dy) in OUTLINE_OFFSETS.iter() {
+                r.copy(mask, src, Some(offset(dx, dy)));
+            }
+            return;
+        }
+
+        // without render targets, fall back to tinted copies
+        let layers = self.layers.iter().filter(|l| l.visible).map(|l| &l.sprite_map);
+        for t in Some(&self.sprite_map).into_iter().chain(layers) {
+            let mut tx = t.borrow_mut();
+            tx.set_color_mod(red, green, blue);
+            for &(dx, dy) in OUTLINE_OFFSETS.iter() {
+                r.copy(&tx, src, Some(offset(dx, dy)));
+            }
+            tx.set_color_mod(255, 255, 255);
+        }
+    }
+}
+
+/// Directions the sprite is offset in to draw an outline.
+const OUTLINE_OFFSETS: [(i32, i32); 8] = [
+    (-1, -1), (0, -1), (1, -1),
+    (-1, 0), (1, 0),
+    (-1, 1), (0, 1), (1, 1),
+];
+
+/// An outline drawn around an entity, e.g. to highlight something
+/// interactable, an editor selection or a boss telegraph. It is made
+/// of copies of the sprite's silhouette in `color`, offset by
+/// `thickness` pixels in 8 directions.
+#[derive(Clone, Copy)]
+pub struct Outline {
+    pub color: Color,
+    pub thickness: i32,
+}
+
+/// A sprite map composited over an entity's base sprite map
+/// using the same frame rects, e.g. armour, weapons or hair.
+pub struct SpriteLayer {
+    pub name: String,
+    pub sprite_map: Rc<RefCell<Texture>>,
+    pub visible: bool,
 }
 
 /// Contains all the data for animating a sprite.
@@ -153,18 +354,69 @@ pub struct Animation {
     pub dir_to_frames: HashMap<Direction, u8>,
     /// A `HashMap` that contains offsets for sprites in the
     /// sprite map.
-    pub dir_to_offset: HashMap<Direction, Point>,
+    pub dir_to_offset: HashMap<Direction, Vec2>,
     /// A `HashMap` that holds the `y`-offset for each `Direction`
     /// in the sprite map.
     pub dir_to_pos: HashMap<Direction, u8>,
     /// Whether the animation needs to be run forwards or backwards.
     pub reverse: bool,
+    /// Optional secondary idle animations.
+    pub idle: Option<IdleVariations>,
+    /// A `HashMap` from a `(from, to)` direction change to the number
+    /// of frames to cross-fade the outgoing frame over the incoming one.
+    pub blend_frames: HashMap<(Direction, Direction), u8>,
+    /// The cross-fade currently in progress, if any.
+    pub blend: Option<Blend>,
+    /// Optional aim-dependent upper body overlay.
+    pub aim: Option<AimOverlay>,
+}
+
+/// Directions an entity's upper body can aim in.
+#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+pub enum Aim {
+    Up,
+    UpForward,
+    Forward,
+    DownForward,
+    Down,
+}
+
+/// Splits each frame horizontally so that the upper body is drawn
+/// from an aim-dependent region of the sprite map, while the legs
+/// keep playing the current animation.
+pub struct AimOverlay {
+    /// Height, from the top of the frame, of the upper body.
+    pub split: u32,
+    /// A `HashMap` from `Direction` and `Aim` to the offset of the
+    /// upper body frame in the sprite map. Directions without an
+    /// entry are drawn normally.
+    pub offsets: HashMap<(Direction, Aim), Vec2>,
+    /// The current aim, or `None` to draw frames normally.
+    pub aim: Option<Aim>,
+}
+
+impl AimOverlay {
+    pub fn new(split: u32, offsets: HashMap<(Direction, Aim), Vec2>) -> Self {
+        AimOverlay {
+            split: split,
+            offsets: offsets,
+            aim: None,
+        }
+    }
+}
+
+/// An in-progress cross-fade between two animations.
+pub struct Blend {
+    /// The outgoing frame's rect in the sprite map.
+    pub from_rect: Rect,
+    pub frames_left: u8,
+    pub frames: u8,
 }
 
 impl Animation {
     pub fn new(dtal: HashMap<Direction, u8>,
                dtf: HashMap<Direction, u8>,
-               dto: HashMap<Direction, Point>,
+               dto: HashMap<Direction, Vec2>,
                dtp: HashMap<Direction, u8>,
                reverse: bool) -> Self {
         Animation {
@@ -175,8 +427,165 @@ impl Animation {
             dir_to_offset: dto,
             dir_to_pos: dtp,
             reverse: reverse,
+            idle: None,
+            blend_frames: HashMap::new(),
+            blend: None,
+            aim: None,
         }
     }
+
+    pub fn set_aim_overlay(&mut self, ao: AimOverlay) {
+        self.aim = Some(ao);
+    }
+
+    /// Set the direction the upper body aims in, if this
+    /// animation has an `AimOverlay`.
+    pub fn set_aim(&mut self, a: Option<Aim>) {
+        if let Some(ref mut ao) = self.aim {
+            ao.aim = a;
+        }
+    }
+
+    /// Returns the upper body offset and split height to use
+    /// for `d`, if the entity is currently aiming.
+    fn aim_offset(&self, d: &Direction) -> Option<(&Vec2, u32)> {
+        match self.aim {
+            Some(AimOverlay{aim: Some(ref a), ref offsets, split}) =>
+                offsets.get(&(d.clone(), a.clone())).map(|off| (off, split)),
+            _ => None,
+        }
+    }
+
+    /// Cross-fade over `frames` frames whenever the direction
+    /// changes from `from` to `to`.
+    pub fn set_blend(&mut self, from: Direction, to: Direction, frames: u8) {
+        self.blend_frames.insert((from, to), frames);
+    }
+
+    fn start_blend(&mut self, from: &Direction, to: &Direction, from_rect: Rect) {
+        if from == to {
+            return;
+        }
+        if let Some(&frames) = self.blend_frames.get(&(from.clone(), to.clone())) {
+            if frames > 0 {
+                self.blend = Some(Blend {
+                    from_rect: from_rect,
+                    frames_left: frames,
+                    frames: frames,
+                });
+            }
+        }
+    }
+
+    fn update_blend(&mut self) {
+        let done = match self.blend {
+            Some(ref mut b) => {
+                b.frames_left -= 1;
+                b.frames_left == 0
+            },
+            None => false,
+        };
+        if done {
+            self.blend = None;
+        }
+    }
+
+    pub fn set_idle_variations(&mut self, iv: IdleVariations) {
+        self.idle = Some(iv);
+    }
+
+    /// Returns the animation length, frame count, sprite map offset
+    /// and `y`-offset to use for `d`, taking any playing idle
+    /// variation into account. Sprite maps without a `Climb`
+    /// animation use the `Up` one while climbing.
+    fn frame_data(&self, d: &Direction) -> (u8, u8, &Vec2, u8) {
+        if let Some(v) = self.idle.as_ref().and_then(|iv| iv.playing()) {
+            return (v.anim_len, v.frames, &v.offset, v.pos);
+        }
+        let d = if *d == Direction::Climb && !self.dir_to_pos.contains_key(d) { &Direction::Up } else { d };
+        (*self.dir_to_anim_len.get(d).unwrap(),
+         *self.dir_to_frames.get(d).unwrap(),
+         self.dir_to_offset.get(d).unwrap(),
+         *self.dir_to_pos.get(d).unwrap())
+    }
+
+    /// Advance the idle variation state. Returns `true` if a
+    /// variation has just started playing.
+    fn update_idle(&mut self, idle: bool) -> bool {
+        if let Some(ref mut iv) = self.idle {
+            if !idle {
+                iv.idle_ticks = 0;
+                iv.current = None;
+            } else if iv.current.is_none() && !iv.variations.is_empty() {
+                iv.idle_ticks += 1;
+                if iv.idle_ticks >= iv.after {
+                    let n = iv.variations.len();
+                    iv.current = Some(iv.rng.gen_range(0, n));
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Stop any playing idle variation, returning `true` if
+    /// one was playing.
+    fn finish_idle_variation(&mut self) -> bool {
+        if let Some(ref mut iv) = self.idle {
+            if iv.current.is_some() {
+                iv.current = None;
+                iv.idle_ticks = 0;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// A secondary idle animation (e.g. looking around, yawning)
+/// played in place of the base idle animation.
+pub struct IdleVariation {
+    /// Offset of the animation in the sprite map.
+    pub offset: Vec2,
+    /// `y`-offset of the animation in the sprite map.
+    pub pos: u8,
+    /// Number of frames in the animation.
+    pub frames: u8,
+    /// Animation length in frames.
+    pub anim_len: u8,
+}
+
+/// Plays a randomly chosen `IdleVariation` once an entity has
+/// been standing still for `after` ticks, then returns to the
+/// base idle animation. A tick is one call to `update`, so the
+/// delay doesn't depend on how often the game is drawn.
+pub struct IdleVariations {
+    pub after: u32,
+    pub variations: Vec<IdleVariation>,
+    /// How many ticks the entity has been idle since the
+    /// last variation finished.
+    pub idle_ticks: u32,
+    /// Index into `variations` of the one currently playing.
+    pub current: Option<usize>,
+    rng: XorShiftRng,
+}
+
+impl IdleVariations {
+    /// Create a new `IdleVariations`. The same `seed` always
+    /// picks the same sequence of variations.
+    pub fn new(after: u32, variations: Vec<IdleVariation>, seed: u32) -> Self {
+        IdleVariations {
+            after: after,
+            variations: variations,
+            idle_ticks: 0,
+            current: None,
+            rng: XorShiftRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, seed]),
+        }
+    }
+
+    fn playing(&self) -> Option<&IdleVariation> {
+        self.current.map(|i| &self.variations[i])
+    }
 }
 
 /// A game entity that moves and is animated.
@@ -185,8 +594,8 @@ pub struct MoveableEntity {
     pub dir: Direction,
     /// The last `Direction` the entity was going.
     pub l_dir: Direction,
-    pub v: Velocity,
-    pub a: Acceleration,
+    pub v: Vec2,
+    pub a: Vec2,
     pub anim: Option<Animation>,
 }
 
@@ -195,13 +604,13 @@ impl MoveableEntity {
     /// Number of frames for `Up`, `Down`, `Left` and `Right`
     /// animations are passed through `uc`, `dc`, `lc`,
     /// `rc`.
-    pub fn new(p: Point,
-               cr: Rect,
-               t: Rc<Texture>,
+    pub fn new(p: Vec2,
+               cr: physics::Rect,
+               t: Rc<RefCell<Texture>>,
                dr: Option<Rect>,
                d: Direction,
-               v: Velocity,
-               a: Acceleration,
+               v: Vec2,
+               a: Vec2,
                anim: Option<Animation>) -> Self {
         MoveableEntity {
             en: Entity::new(p, cr, t, dr),
@@ -213,20 +622,89 @@ impl MoveableEntity {
         }
     }
 
-    pub fn keep_on_screen(&mut self, w: u32, h: u32) {
-        if (self.en.collision_rect.x() as i64 + self.en.pos.x) < 0 {
-            self.en.pos.x = -self.en.collision_rect.x() as i64;
-        } else if (self.en.collision_rect.x() as i64 + self.en.pos.x + self.en.collision_rect.width() as i64) > w as i64 {
-            self.en.pos.x = w as i64 - (self.en.collision_rect.width() as i64 + self.en.collision_rect.x() as i64);
+    /// Apply a map's out-of-bounds policy `b` to the entity, for
+    /// a map of `w` x `h` pixels. Returns whether the entity fell
+    /// below the kill plane.
+    pub fn apply_bounds(&mut self, b: &map::Bounds, w: u32, h: u32) -> bool {
+        let (w, h) = (w as f64, h as f64);
+        let (left, width) = (self.en.collision_rect.x() as f64, self.en.collision_rect.width() as f64);
+        let height = self.en.collision_rect.height() as f64;
+        match b.x {
+            map::Edge::Block => {
+                if (left + self.en.pos.x) < 0.0 {
+                    self.en.pos.x = -left;
+                } else if (left + self.en.pos.x + width) > w {
+                    self.en.pos.x = w - (width + left);
+                }
+            },
+            map::Edge::Wrap => {
+                let centre = self.en.pos.x + left + width/2.0;
+                let shift = if centre < 0.0 { w } else if centre >= w { -w } else { 0.0 };
+                self.en.pos.x += shift;
+                self.en.prev_pos.x += shift;
+            },
+            map::Edge::Open => (),
         }
-        if self.en.pos.y < 0 {
-            self.en.pos.y = 0;
-        } else if (self.en.pos.y + self.en.collision_rect.height() as i64) > h as i64 {
-            self.en.pos.y = h as i64 - self.en.collision_rect.height() as i64;
-            match self.dir {
-                Direction::Up | Direction::DoubleUp => self.change_dir(Direction::Landed),
-                _ => (),
+        match b.y {
+            map::Edge::Block => {
+                if self.en.pos.y < 0.0 {
+                    self.en.pos.y = 0.0;
+                } else if b.kill_below.is_none() && (self.en.pos.y + height) > h {
+                    self.en.pos.y = h - height;
+                    match self.dir {
+                        Direction::Up | Direction::DoubleUp => self.change_dir(Direction::Landed),
+                        _ => (),
+                    }
+                }
+            },
+            map::Edge::Wrap => {
+                let centre = self.en.pos.y + height/2.0;
+                let shift = if centre < 0.0 { h } else if centre >= h && b.kill_below.is_none() { -h } else { 0.0 };
+                self.en.pos.y += shift;
+                self.en.prev_pos.y += shift;
+            },
+            map::Edge::Open => (),
+        }
+        b.kill_below.map_or(false, |k| self.en.pos.y > k as f64)
+    }
+
+    /// Take back the part of the entity's move from `from` that
+    /// took it into `map`'s solid tiles, one axis at a time,
+    /// stopping it against the tiles it ran into.
+    pub fn collide_with_map(&mut self, map: &map::Map, from: Vec2) {
+        let to = self.en.pos;
+        let cr = self.en.collision_rect;
+        let (tw, th) = (map.tile_width as f64, map.tile_height as f64);
+
+        self.en.pos.y = from.y;
+        let tiles = map.solid_tiles_in_rect(&cr.offset(self.en.pos));
+        if !tiles.is_empty() && to.x != from.x {
+            if to.x > from.x {
+                let col = tiles.iter().map(|&(col, _)| col).min().unwrap();
+                self.en.pos.x = (col as f64 * tw - (cr.x + cr.width as i32) as f64).max(from.x);
+            } else {
+                let col = tiles.iter().map(|&(col, _)| col).max().unwrap();
+                self.en.pos.x = ((col + 1) as f64 * tw - cr.x as f64).min(from.x);
             }
+            self.v.x = 0.0;
+            self.a.x = 0.0;
+        }
+
+        self.en.pos.y = to.y;
+        let tiles = map.solid_tiles_in_rect(&cr.offset(self.en.pos));
+        if !tiles.is_empty() && to.y != from.y {
+            if to.y > from.y {
+                let row = tiles.iter().map(|&(_, row)| row).min().unwrap();
+                self.en.pos.y = (row as f64 * th - (cr.y + cr.height as i32) as f64).max(from.y);
+                match self.dir {
+                    Direction::Up | Direction::DoubleUp => self.change_dir(Direction::Landed),
+                    _ => (),
+                }
+            } else {
+                let row = tiles.iter().map(|&(_, row)| row).max().unwrap();
+                self.en.pos.y = ((row + 1) as f64 * th - cr.y as f64).min(from.y);
+            }
+            self.v.y = 0.0;
         }
     }
 
@@ -237,17 +715,30 @@ impl MoveableEntity {
         }
     }
 
+    /// Start cross-fading from the frame that was last drawn, if
+    /// a blend is configured for the change from `from` to the
+    /// current direction.
+    fn start_blend(&mut self, from: &Direction) {
+        if let (Some(dr), &mut Some(ref mut anim)) = (self.en.draw_rect, &mut self.anim) {
+            anim.start_blend(from, &self.dir, dr);
+        }
+    }
+
     pub fn change_dir(&mut self, d: Direction) {
         if d == Direction::Landed {
+            let from = self.dir.clone();
             self.dir = self.l_dir.clone();
             self.l_dir = d;
+            self.start_blend(&from);
             return;
-        } else if self.dir == Direction::Up || self.dir == Direction::DoubleUp {
+        } else if self.dir == Direction::Up || self.dir == Direction::DoubleUp || self.dir == Direction::Climb {
             return;
         }
 
         self.l_dir = self.dir.clone();
         self.dir = d.clone();
+        let from = self.l_dir.clone();
+        self.start_blend(&from);
 
         if d == Direction::StillLeft || d == Direction::StillRight {
             self.reset_anim();
@@ -259,18 +750,20 @@ impl MoveableEntity {
 /// player-specific mechanics and methods.
 pub struct Player {
     pub me: MoveableEntity,
+    /// Where the player comes back after dying.
+    pub spawn: Vec2,
 }
 
 impl Player {
-    pub fn new(p: Point,
-               cr: Rect,
-               t: Rc<Texture>,
+    pub fn new(p: Vec2,
+               cr: physics::Rect,
+               t: Rc<RefCell<Texture>>,
                dr: Option<Rect>,
                d: Direction,
                dtp: HashMap<Direction, u8>,
                dtal: HashMap<Direction, u8>,
                dtf: HashMap<Direction, u8>,
-               dto: HashMap<Direction, Point>,
+               dto: HashMap<Direction, Vec2>,
                reverse: bool) -> Self {
         Player {
             me: MoveableEntity::new(
@@ -279,8 +772,8 @@ impl Player {
                 t,
                 dr,
                 d,
-                Velocity::zero(),
-                Acceleration::zero(),
+                Vec2::zero(),
+                Vec2::zero(),
                 Some(Animation::new(
                     dtal,
                     dtf,
@@ -289,11 +782,61 @@ impl Player {
                     reverse
                 ))
             ),
+            spawn: p,
         }
     }
 
-    pub fn keep_on_screen(&mut self, w: u32, h: u32) {
-        self.me.keep_on_screen(w, h);
+    /// Put the player back at `spawn`, at rest.
+    pub fn respawn(&mut self) {
+        self.me.en.pos = self.spawn;
+        self.me.en.prev_pos = self.spawn;
+        self.me.v = Vec2::zero();
+        self.me.a = Vec2::zero();
+    }
+
+    /// Where the player holds on to things, in world pixels:
+    /// the top centre of their collision rect.
+    pub fn hands(&self) -> Vec2 {
+        let cr = self.me.en.collision_rect;
+        self.me.en.pos + Vec2::new(cr.x() as f64 + cr.width() as f64/2.0, cr.y() as f64)
+    }
+
+    /// Move the player so their hands are at `p`.
+    pub fn set_hands(&mut self, p: Vec2) {
+        let d = p - self.hands();
+        self.me.en.pos += d;
+    }
+
+    /// Move the player to `start` and make it their spawn point.
+    pub fn place(&mut self, start: &map::PlayerStart) {
+        let cr = self.me.en.collision_rect;
+        self.spawn = start.pos - Vec2::new(cr.x() as f64 + cr.width() as f64/2.0,
+                                           cr.y() as f64 + cr.height() as f64);
+        self.respawn();
+        self.me.dir = start.facing.clone();
+        self.me.l_dir = start.facing.clone();
+    }
+
+    /// Switch to the `Climb` animation, remembering which
+    /// way the player was facing for when they get off.
+    pub fn start_climbing(&mut self) {
+        let facing = match (&self.me.dir, &self.me.l_dir) {
+            (&Direction::Left, _) | (&Direction::StillLeft, _) => Direction::StillLeft,
+            (&Direction::Right, _) | (&Direction::StillRight, _) => Direction::StillRight,
+            (_, &Direction::Left) | (_, &Direction::StillLeft) => Direction::StillLeft,
+            _ => Direction::StillRight,
+        };
+        self.me.l_dir = facing;
+        self.me.dir = Direction::Climb;
+        self.me.reset_anim();
+    }
+
+    /// Get off a ladder, facing the way the player was
+    /// before they started climbing.
+    pub fn stop_climbing(&mut self) {
+        if self.me.dir == Direction::Climb {
+            self.me.change_dir(Direction::Landed);
+        }
     }
 
     pub fn jump(&mut self) {
@@ -312,109 +855,726 @@ impl Player {
 
 /// Holds pure game data, as opposed to `System`,
 /// which holds system data like the frame counter.
-pub struct Game<'a> {
+pub struct Game {
     pub running: bool,
     pub debug: bool,
-    pub current_map: Option<&'a mut map::Map>,
+    pub current_map: Option<map::Map>,
     pub camera: Camera,
     pub player: Player,
+    pub teleport: teleport::TeleportState,
+    /// Narrative flags and counters, for conditional content.
+    pub flags: flags::Flags,
+    /// Names of post-processing effects to trigger, collected
+    /// during a tick for `System` to pass on to its `EffectChain`.
+    pub effects: Vec<String>,
+    /// The map the player just warped to, and the spawn point to
+    /// arrive at, for `System` to load and pass to `change_map`.
+    pub pending_warp: Option<(PathBuf, Option<String>)>,
+    /// The player's ride along a zip line, if they're on one.
+    pub zip: Option<grapple::ZipRide>,
+    /// Whether the player is on a ladder. While they are, gravity
+    /// doesn't pull them and their vertical velocity is set by
+    /// whoever handles input, e.g. `System` from Up and Down.
+    pub climbing: bool,
+    /// Oxygen, heat and other meters drained by zones in the map.
+    pub meters: survival::Meters,
+    /// Where the player is aiming.
+    pub reticle: aim::Reticle,
+    /// Systems added by the game, run alongside the built-in ones.
+    pub systems: Registry,
+    /// The world is drawn into this before it's rotated, while
+    /// the camera is rotated.
+    rotated_view: Option<Texture>,
 }
 
-impl<'a> Game<'a> {
+impl Game {
     /// Create a new `Game`.
-    pub fn new(db: bool, current_map: Option<&'a mut map::Map>, cam: Camera, p: Player) -> Self {
+    pub fn new(db: bool, current_map: Option<map::Map>, cam: Camera, p: Player) -> Self {
         Game {
             running: true,
             debug: db,
             current_map: current_map,
             camera: cam,
             player: p,
+            teleport: teleport::TeleportState::new(),
+            flags: flags::Flags::new(),
+            effects: vec!(),
+            pending_warp: None,
+            zip: None,
+            climbing: false,
+            meters: survival::Meters::new(),
+            reticle: aim::Reticle::new(),
+            systems: Registry::new(),
+            rotated_view: None,
         }
     }
 
-    pub fn set_map(&mut self, map: &'a mut map::Map) {
+    /// Make `map` the current map, placing the player and
+    /// camera at its `player_start`, if it has one.
+    pub fn set_map(&mut self, map: map::Map) {
+        self.enter_map(map, None);
+    }
+
+    /// Unload the current map and switch to `map`, placing the player
+    /// at its spawn point named `spawn`, or its `player_start` if
+    /// there's no such spawn point. The camera and any teleport in
+    /// progress are reset.
+    pub fn change_map(&mut self, map: map::Map, spawn: Option<&str>) {
+        self.current_map = None;
+        self.teleport = teleport::TeleportState::new();
+        self.pending_warp = None;
+        self.let_go();
+        self.player.me.v = Vec2::zero();
+        self.player.me.a = Vec2::zero();
+        self.camera.pos = Vec2::zero();
+        self.camera.prev_pos = self.camera.pos;
+        self.enter_map(map, spawn);
+    }
+
+    /// Replace the current map with `map`, a reloaded copy of it.
+    /// The player and camera stay where they are if the player is
+    /// still inside the map; otherwise they're placed at its
+    /// `player_start`, as by `change_map`.
+    pub fn reload_map(&mut self, map: map::Map) {
+        let p = self.player.me.en.pos;
+        if p.x < 0.0 || p.y < 0.0 || p.x >= map.pixel_width() as f64 || p.y >= map.pixel_height() as f64 {
+            self.change_map(map, None);
+            return;
+        }
+        self.teleport = teleport::TeleportState::new();
+        self.let_go();
+        self.current_map = Some(map);
+        self.update_camera();
+        self.camera.prev_pos = self.camera.pos;
+    }
+
+    fn enter_map(&mut self, map: map::Map, spawn: Option<&str>) {
+        let start = map.spawn_point(spawn).cloned();
         self.current_map = Some(map);
+        if let Some(start) = start {
+            self.player.place(&start);
+            self.camera.pos = match start.camera {
+                Some(pos) => pos,
+                None => start.pos - Vec2::new(self.camera.width/2.0, self.camera.height/2.0),
+            };
+            // keep the camera within the map from the first frame
+            self.update_camera();
+            self.camera.prev_pos = self.camera.pos;
+        }
     }
 
+    /// Clear the screen to the current map's background color.
     pub fn clear(&self, r: &mut Renderer) {
+        let draw_col = r.draw_color();
+        r.set_draw_color(self.current_map.as_ref().map_or(Color::RGB(0, 0, 0), |m| m.clear_color()));
         r.clear();
+        r.set_draw_color(draw_col);
     }
 
     pub fn flip_buffer(&self, r: &mut Renderer) {
         r.present();
     }
 
-    pub fn keep_on_screen(&mut self, w: u32, h: u32) {
-        self.player.keep_on_screen(w, h);
+    /// Apply the current map's out-of-bounds policy to the player,
+    /// respawning them if they fell below the kill plane.
+    pub fn apply_bounds(&mut self) {
+        let (b, w, h) = match self.current_map {
+            Some(ref map) => (map.bounds, map.pixel_width(), map.pixel_height()),
+            None => return,
+        };
+        let before = self.player.me.en.pos;
+        if self.player.me.apply_bounds(&b, w, h) {
+            self.player.respawn();
+            self.let_go();
+            return;
+        }
+        // follow the player across a wrapping edge without
+        // the camera sweeping over the whole map
+        let d = self.player.me.en.pos - before;
+        if b.x == map::Edge::Wrap && d.x.abs() >= w as f64 / 2.0 {
+            self.camera.pos.x += d.x;
+            self.camera.prev_pos.x += d.x;
+        }
+        if b.y == map::Edge::Wrap && d.y.abs() >= h as f64 / 2.0 {
+            self.camera.pos.y += d.y;
+            self.camera.prev_pos.y += d.y;
+        }
+    }
+
+    /// Send the player to the partner of any teleporter they're
+    /// touching, unless they've only just teleported.
+    fn update_teleporters(&mut self) {
+        if self.teleport.cooldown > 0 {
+            self.teleport.cooldown -= 1;
+        }
+        let (src, to, dest) = {
+            let map = match self.current_map {
+                Some(ref map) => map,
+                None => return,
+            };
+            let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+
+            if let Some(i) = self.teleport.arrived_at {
+                if map.teleporters.get(i).map_or(true, |t| !t.rect.has_intersection(&player)) {
+                    self.teleport.arrived_at = None;
+                }
+            }
+            if self.teleport.cooldown > 0 {
+                return;
+            }
+
+            let arrived_at = self.teleport.arrived_at;
+            let from = match (0..map.teleporters.len())
+                .find(|&i| Some(i) != arrived_at && map.teleporters[i].rect.has_intersection(&player)) {
+                Some(i) => i,
+                None => return,
+            };
+            match teleport::partner(&map.teleporters, from) {
+                Some(to) => (map.teleporters[from].clone(), to, map.teleporters[to].clone()),
+                None => return,
+            }
+        };
+
+        let arrival = dest.arrival_pos(&self.player.me.en.collision_rect);
+        let d = arrival - self.player.me.en.pos;
+        self.player.me.en.pos = arrival;
+        self.player.me.en.prev_pos = arrival;
+        self.let_go();
+        self.player.me.v = src.velocity.apply(self.player.me.v);
+
+        match src.camera {
+            teleport::CameraMode::Snap => {
+                self.camera.pos += d;
+                self.camera.prev_pos = self.camera.pos;
+            },
+            teleport::CameraMode::Pan(ticks) => self.teleport.pan = Some((self.camera.pos, 0, ticks)),
+        }
+        self.teleport.cooldown = src.cooldown;
+        self.teleport.arrived_at = Some(to);
+        if let Some(effect) = src.effect {
+            self.effects.push(effect);
+        }
+    }
+
+    /// Note the map of any warp the player is touching,
+    /// for `System` to switch to.
+    fn update_warps(&mut self) {
+        let map = match self.current_map {
+            Some(ref map) => map,
+            None => return,
+        };
+        let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+        if let Some(w) = map.warps.iter().find(|w| w.rect.has_intersection(&player)) {
+            self.pending_warp = Some((map.warp_path(w), w.target.clone()));
+        }
+    }
+
+    /// Respawn the player if they touch a hazard.
+    fn update_hazards(&mut self) {
+        let hit = match self.current_map {
+            Some(ref map) => {
+                let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+                map.hazards.iter().any(|h| h.hits(&player))
+            },
+            None => false,
+        };
+        if hit {
+            self.player.respawn();
+            self.let_go();
+        }
+    }
+
+    /// Drain the meters of any zones the player is in, respawning
+    /// them with full meters if one runs out.
+    fn update_meters(&mut self) {
+        let empty = match self.current_map {
+            Some(ref map) => {
+                let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+                self.meters.update(&map.meter_zones, &player)
+            },
+            None => false,
+        };
+        if empty {
+            self.player.respawn();
+            self.let_go();
+            self.meters.refill();
+        }
+    }
+
+    /// Run the systems in `systems` added at `stage`, for a tick
+    /// of `dt` milliseconds. Systems they add run from the next tick.
+    pub fn update_systems(&mut self, stage: Stage, dt: f64) {
+        // taken out of the registry so they can be given the game
+        let mut updateables = mem::replace(&mut self.systems.updateables, vec!());
+        for &mut (s, ref mut u) in updateables.iter_mut() {
+            if s == stage {
+                u.update(self, dt);
+            }
+        }
+        updateables.extend(self.systems.updateables.drain(..));
+        self.systems.updateables = updateables;
+    }
+
+    /// Drop off any zip line or ladder the player is holding on to.
+    fn let_go(&mut self) {
+        self.zip = None;
+        if self.climbing {
+            self.climbing = false;
+            self.player.stop_climbing();
+        }
+    }
+
+    /// Start climbing, if the player overlaps a climbable tile.
+    /// Returns whether the player is now climbing.
+    pub fn grab_ladder(&mut self) -> bool {
+        if self.climbing {
+            return true;
+        }
+        let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+        if self.zip.is_some() || !self.current_map.as_ref().map_or(false, |m| m.is_climbable(&player)) {
+            return false;
+        }
+        self.climbing = true;
+        self.player.me.v = Vec2::zero();
+        self.player.me.a = Vec2::zero();
+        self.player.start_climbing();
+        true
+    }
+
+    /// Get off the ladder, and jump off it if `jump`.
+    pub fn release_ladder(&mut self, jump: bool) {
+        if !self.climbing {
+            return;
+        }
+        self.let_go();
+        if jump {
+            self.player.jump();
+        }
+    }
+
+    /// Move the player along their ladder at their vertical
+    /// velocity, without gravity, getting off once they're no
+    /// longer on a climbable tile.
+    fn update_climbing(&mut self) {
+        // inputs don't build up while climbing
+        self.player.me.a = Vec2::zero();
+        self.player.me.v.x = 0.0;
+        let v = self.player.me.v;
+        self.player.me.en.pos += v;
+        self.player.me.update();
+        let player = self.player.me.en.collision_rect.offset(self.player.me.en.pos);
+        if !self.current_map.as_ref().map_or(false, |m| m.is_climbable(&player)) {
+            self.release_ladder(false);
+        }
+    }
+
+    /// Grab the zip line within reach of the player's hands, if
+    /// there is one. Returns whether the player is now riding one.
+    pub fn grab_zip_line(&mut self) -> bool {
+        if self.zip.is_some() {
+            return true;
+        }
+        let ride = match self.current_map {
+            Some(ref map) => match grapple::zip_line_in_reach(&map.zip_lines, self.player.hands()) {
+                Some((line, t)) => grapple::ZipRide::new(&map.zip_lines, line, t, self.player.me.v),
+                None => return false,
+            },
+            None => return false,
+        };
+        self.zip = Some(ride);
+        self.player.me.v = Vec2::zero();
+        self.player.me.a = Vec2::zero();
+        true
+    }
+
+    /// Let go of the zip line, keeping the ride's velocity,
+    /// and jump off it if `jump`.
+    pub fn release_zip_line(&mut self, jump: bool) {
+        let ride = match self.zip.take() {
+            Some(ride) => ride,
+            None => return,
+        };
+        if let Some(line) = self.current_map.as_ref().and_then(|m| m.zip_lines.get(ride.line)) {
+            self.player.me.v = ride.velocity(line);
+        }
+        if jump {
+            self.player.jump();
+        }
+    }
+
+    /// Slide the player along their zip line, letting
+    /// go once they reach an end.
+    fn update_zip_line(&mut self) {
+        let mut ride = match self.zip {
+            Some(ride) => ride,
+            None => return,
+        };
+        let (on, hands) = match self.current_map.as_ref().and_then(|m| m.zip_lines.get(ride.line)) {
+            Some(line) => (ride.step(line), line.point_at(ride.t.max(0.0).min(1.0))),
+            None => (false, self.player.hands()),
+        };
+        self.zip = Some(ride);
+        // inputs don't build up while hanging
+        self.player.me.a = Vec2::zero();
+        self.player.set_hands(hands);
+        self.player.me.update();
+        if !on {
+            self.release_zip_line(false);
+        }
+    }
+
+    /// Returns the unit vector from the player's hands to where
+    /// they're aiming, for shots and grappling hooks.
+    pub fn aim(&self) -> Vec2 {
+        self.reticle.aim_from(self.player.hands())
+    }
+
+    /// Point the reticle through the camera, snapping it
+    /// to grapple anchors.
+    fn update_aim(&mut self) {
+        let targets: Vec<Vec2> = match self.current_map {
+            Some(ref map) => map.grapple_anchors.iter().map(|a| a.pos).collect(),
+            None => vec!(),
+        };
+        self.reticle.update(&self.camera, &targets);
     }
 
     fn update_camera(&mut self) {
         let (camera_left, camera_right, camera_top, camera_bottom) = (
-            self.camera.pos.x + self.camera.collision_rect.x() as i64,
-            self.camera.pos.x + self.camera.collision_rect.x() as i64 + self.camera.collision_rect.width() as i64,
-            self.camera.pos.y + self.camera.collision_rect.y() as i64,
-            self.camera.pos.y + self.camera.collision_rect.y() as i64 + self.camera.collision_rect.height() as i64,
+            self.camera.pos.x + self.camera.collision_rect.x() as f64,
+            self.camera.pos.x + self.camera.collision_rect.x() as f64 + self.camera.collision_rect.width() as f64,
+            self.camera.pos.y + self.camera.collision_rect.y() as f64,
+            self.camera.pos.y + self.camera.collision_rect.y() as f64 + self.camera.collision_rect.height() as f64,
         );
         let (player_left, player_right, player_top, player_bottom) = (
-            self.player.me.en.pos.x + self.player.me.en.collision_rect.x() as i64,
-            self.player.me.en.pos.x + self.player.me.en.collision_rect.x() as i64 + self.player.me.en.collision_rect.width() as i64,
-            self.player.me.en.pos.y + self.player.me.en.collision_rect.y() as i64,
-            self.player.me.en.pos.y + self.player.me.en.collision_rect.y() as i64 + self.player.me.en.collision_rect.height() as i64,
+            self.player.me.en.pos.x + self.player.me.en.collision_rect.x() as f64,
+            self.player.me.en.pos.x + self.player.me.en.collision_rect.x() as f64 + self.player.me.en.collision_rect.width() as f64,
+            self.player.me.en.pos.y + self.player.me.en.collision_rect.y() as f64,
+            self.player.me.en.pos.y + self.player.me.en.collision_rect.y() as f64 + self.player.me.en.collision_rect.height() as f64,
         );
 
-        let map = self.current_map.as_ref().unwrap().clone();
+        let (bounds, map_width, map_height) = match self.current_map {
+            Some(ref map) => (map.bounds, map.pixel_width() as f64, map.pixel_height() as f64),
+            None => return,
+        };
 
         if player_right > camera_right {
-            self.camera.pos.x = player_right - self.camera.collision_rect.width() as i64 - self.camera.collision_rect.x() as i64;
+            self.camera.pos.x = player_right - self.camera.collision_rect.width() as f64 - self.camera.collision_rect.x() as f64;
         } else if player_left < camera_left {
-            self.camera.pos.x = player_left - self.camera.collision_rect.x() as i64;
+            self.camera.pos.x = player_left - self.camera.collision_rect.x() as f64;
         }
 
         if player_bottom > camera_bottom {
-            self.camera.pos.y = player_bottom - self.camera.collision_rect.height() as i64;
+            self.camera.pos.y = player_bottom - self.camera.collision_rect.height() as f64;
         } else if player_top < camera_top {
             self.camera.pos.y = player_top;
         }
 
-        // keep the camera within the map
-        if self.camera.pos.x+self.camera.width > map.pixel_width() as i64 {
-            self.camera.pos.x = map.pixel_width() as i64 - self.camera.width;
-        } else if self.camera.pos.x < 0 {
-            self.camera.pos.x = 0;
+        // keep the camera within the map, except across wrapping edges
+        if bounds.x != map::Edge::Wrap {
+            if self.camera.pos.x+self.camera.width > map_width {
+                self.camera.pos.x = map_width - self.camera.width;
+            } else if self.camera.pos.x < 0.0 {
+                self.camera.pos.x = 0.0;
+            }
+        }
+
+        if bounds.y != map::Edge::Wrap {
+            if self.camera.pos.y+self.camera.height > map_height {
+                self.camera.pos.y = map_height - self.camera.height;
+            } else if self.camera.pos.y < 0.0 {
+                self.camera.pos.y = 0.0;
+            }
         }
 
-        if self.camera.pos.y+self.camera.height > map.pixel_height() as i64 {
-            self.camera.pos.y = map.pixel_height() as i64 - self.camera.height;
-        } else if self.camera.pos.y < 0 {
-            self.camera.pos.y = 0;
+        // scroll from where the camera was before a teleport
+        if let Some((from, tick, ticks)) = self.teleport.pan {
+            let tick = tick + 1;
+            self.camera.pos = from.lerp(&self.camera.pos, tick as f64 / ticks as f64);
+            self.teleport.pan = if tick < ticks { Some((from, tick, ticks)) } else { None };
+        }
+    }
+}
+
+/// How `System` paces the frames it draws.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameLimit {
+    /// Draw frames as fast as possible.
+    Uncapped,
+    /// Draw `System::fps` frames per second.
+    Capped,
+    /// Leave pacing to `present`, which waits for vertical sync.
+    /// Only has an effect if the renderer was built with
+    /// `present_vsync`; otherwise it's the same as `Uncapped`.
+    VSync,
+}
+
+impl FrameLimit {
+    /// The mode after this one, for cycling through them. `VSync`
+    /// is left out, as it only works if the renderer was built for
+    /// it; games that build theirs with `present_vsync` can choose
+    /// it with `System::set_frame_limit`.
+    fn next(&self) -> Self {
+        match *self {
+            FrameLimit::Uncapped => FrameLimit::Capped,
+            FrameLimit::Capped | FrameLimit::VSync => FrameLimit::Uncapped,
         }
     }
 }
 
 /// Contains system data like the renderer,
-/// frame counter, fps timer, etc...
+/// frame counter, frame timing, etc...
 pub struct System<'a> {
-    pub game: Game<'a>,
+    pub game: Game,
     pub r: Renderer<'a>,
     pub fc: u8,
+    /// Simulation ticks per second.
+    pub ups: u8,
+    /// Frames drawn per second when the frame limit is `Capped`.
     pub fps: u8,
-    pub timer: Receiver<()>,
+    pub frame_limit: FrameLimit,
+    /// Milliseconds between the last two frames.
+    pub frame_time: f64,
+    /// Frames actually drawn in the last whole second.
+    pub measured_fps: u32,
     pub ev_pump: EventPump,
     pub assets: &'a Path,
+    /// Screen-space effects applied to every frame.
+    pub post: postprocess::EffectChain,
+    last_frame: Instant,
+    /// When the next frame is due, if the frame limit is `Capped`.
+    next_frame: Instant,
+    /// Frames drawn since `second_start`.
+    frames: u32,
+    second_start: Instant,
+    /// Milliseconds of simulation time not yet ticked.
+    accumulator: f64,
+    /// Time spent in each stage of the current frame, or
+    /// the last one for stages the current one hasn't reached.
+    pub times: FrameTimes,
+    /// Time each stage should take at most. Overruns are
+    /// counted and reported once a second.
+    pub budget: FrameTimes,
+    /// Frames since `second_start` in which the update
+    /// and render stages went over budget.
+    overruns: (u32, u32),
+}
+
+/// Returns `d` in milliseconds.
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1000.0 + d.subsec_nanos() as f64 / 1_000_000.0
 }
 
+/// Most simulation time, in milliseconds, caught up on in one
+/// frame, so that a long stall doesn't keep the game ticking
+/// without drawing.
+const MAX_FRAME_TIME: f64 = 250.0;
+
 impl<'a> System<'a> {
-    /// Create a new `System`.
-    pub fn new(g: Game<'a>, r: Renderer<'a>, fps: u8, ep: EventPump, a: &'a Path) -> Self {
+    /// Create a new `System` that simulates `ups` ticks and
+    /// draws at most `fps` frames per second.
+    pub fn new(g: Game, r: Renderer<'a>, ups: u8, fps: u8, ep: EventPump, a: &'a Path) -> Self {
         System {
             game: g,
             r: r,
             fc: 0,
+            ups: ups,
             fps: fps,
-            timer: timer_periodic(1000/fps as u32),
+            frame_limit: FrameLimit::Capped,
+            frame_time: 0.0,
+            measured_fps: 0,
             ev_pump: ep,
             assets: a,
+            post: postprocess::EffectChain::new(),
+            last_frame: Instant::now(),
+            next_frame: Instant::now(),
+            frames: 0,
+            second_start: Instant::now(),
+            accumulator: 0.0,
+            times: FrameTimes::default(),
+            // leave half of each frame for whatever else is running
+            budget: FrameTimes {
+                update: 250.0 / fps.max(1) as f64,
+                render: 250.0 / fps.max(1) as f64,
+            },
+            overruns: (0, 0),
+        }
+    }
+
+    pub fn set_frame_limit(&mut self, l: FrameLimit) {
+        self.frame_limit = l;
+        self.next_frame = Instant::now();
+    }
+
+    /// If the frame limit is `Capped`, wait until the next
+    /// frame is due.
+    fn wait_for_frame(&mut self) {
+        if self.frame_limit != FrameLimit::Capped {
+            return;
+        }
+        let frame = Duration::new(0, 1_000_000_000 / self.fps.max(1) as u32);
+        let now = Instant::now();
+        if now >= self.next_frame {
+            // running late, so don't try to catch up on missed frames
+            self.next_frame = now + frame;
+            return;
+        }
+        // sleep is only accurate to a millisecond or so,
+        // so wake up early and spin for the rest
+        let spin = Duration::from_millis(2);
+        let left = self.next_frame.duration_since(now);
+        if left > spin {
+            thread::sleep(left - spin);
+        }
+        while Instant::now() < self.next_frame {
+            thread::yield_now();
+        }
+        self.next_frame = self.next_frame + frame;
+    }
+
+    /// Update `frame_time` and `measured_fps` for a frame
+    /// starting at `now`.
+    fn record_frame(&mut self, now: Instant) {
+        self.frame_time = millis(now.duration_since(self.last_frame));
+        self.last_frame = now;
+        self.frames += 1;
+        if now.duration_since(self.second_start) >= Duration::from_secs(1) {
+            self.measured_fps = self.frames;
+            self.frames = 0;
+            self.second_start = now;
+            self.report_overruns();
+        }
+    }
+
+    /// Report the stages that went over budget
+    /// since the last report.
+    fn report_overruns(&mut self) {
+        let (update, render) = self.overruns;
+        if update > 0 || render > 0 {
+            self.game.systems.report(Report::OverBudget {
+                budget: self.budget,
+                update: update,
+                render: render,
+            });
         }
+        self.overruns = (0, 0);
+    }
+
+    /// Draw and present a frame, with post-processing.
+    pub fn render(&mut self) {
+        let start = Instant::now();
+        self.post.begin(&mut self.r);
+        self.game.clear(&mut self.r);
+        self.game.draw(&mut self.r);
+        self.post.end(&mut self.r);
+        self.game.flip_buffer(&mut self.r);
+        self.times.render = millis(start.elapsed());
+        if self.times.render > self.budget.render {
+            self.overruns.1 += 1;
+        }
+        self.game.systems.frame_hook(FrameHook::EndRender, &self.times);
+    }
+
+    /// Read the current map's file again and swap it in,
+    /// for editing levels while the game is running.
+    pub fn reload_map(&mut self) {
+        let path = match self.game.current_map.as_ref().and_then(|m| m.path.clone()) {
+            Some(path) => path,
+            None => return,
+        };
+        match map::Map::load(&path, &self.r) {
+            Ok(map) => self.game.reload_map(map),
+            Err(e) => self.game.systems.report(Report::ReloadFailed(path, e)),
+        }
+    }
+
+    /// Advance the simulation by one tick.
+    fn tick(&mut self) {
+        self.fc += 1;
+        if self.fc > self.ups {
+            self.fc = 0;
+        }
+
+        // collected first, as some keys call back into `self`
+        let events: Vec<Event> = self.ev_pump.poll_iter().collect();
+        for event in events {
+            match event {
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                    self.game.running = false
+                },
+                Event::KeyDown{keycode: Some(Keycode::Space), ..} => {
+                    if self.game.zip.is_some() {
+                        self.game.release_zip_line(true);
+                    } else if self.game.climbing {
+                        self.game.release_ladder(true);
+                    } else {
+                        self.game.player.jump();
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::Up), ..} => {
+                    if !self.game.grab_zip_line() {
+                        self.game.grab_ladder();
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::Down), ..} => self.game.release_zip_line(false),
+                Event::KeyDown{keycode: Some(Keycode::F1), ..} => self.post.toggle("scanlines"),
+                Event::KeyDown{keycode: Some(Keycode::F2), ..} => self.post.toggle("vignette"),
+                Event::KeyDown{keycode: Some(Keycode::F3), ..} => self.post.toggle("chromatic"),
+                Event::KeyDown{keycode: Some(Keycode::F4), ..} => {
+                    let l = self.frame_limit.next();
+                    self.set_frame_limit(l);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => self.reload_map(),
+                Event::MouseMotion{x, y, ..} => {
+                    if let Ok(window) = self.r.output_size() {
+                        self.game.reticle.point_at(x, y, window, &self.game.camera);
+                    }
+                },
+                _ => ()
+            }
+        }
+
+        {
+            let me = &mut self.game.player.me;
+            const HORIZONTAL_ACCELERATION: f64 = 9.5;
+            if self.ev_pump.keyboard_state().is_scancode_pressed(Scancode::Left) {
+                me.a.x -= HORIZONTAL_ACCELERATION;
+                me.change_dir(Direction::Left);
+            } else if self.ev_pump.keyboard_state().is_scancode_pressed(Scancode::Right) {
+                me.a.x += HORIZONTAL_ACCELERATION;
+                me.change_dir(Direction::Right);
+            }
+
+            const CLIMB_SPEED: f64 = 6.0;
+            if self.game.climbing {
+                let keys = self.ev_pump.keyboard_state();
+                me.v.y = if keys.is_scancode_pressed(Scancode::Up) {
+                    -CLIMB_SPEED
+                } else if keys.is_scancode_pressed(Scancode::Down) {
+                    CLIMB_SPEED
+                } else {
+                    0.0
+                };
+            }
+        }
+
+        let dt = 1000.0 / self.ups as f64;
+        self.game.update_systems(Stage::PrePhysics, dt);
+        self.game.update();
+        if let Some((path, spawn)) = self.game.pending_warp.take() {
+            match map::Map::load(&path, &self.r) {
+                Ok(map) => self.game.change_map(map, spawn.as_ref().map(|s| &s[..])),
+                Err(e) => self.game.systems.report(Report::WarpFailed(path, e)),
+            }
+        }
+        for effect in self.game.effects.drain(..) {
+            self.post.trigger(&effect);
+        }
+        if let Some(ref mut map) = self.game.current_map {
+            map.update(1000 / self.ups as u32);
+        }
+        self.game.apply_bounds();
+        self.game.update_systems(Stage::PostPhysics, dt);
     }
 }
 
@@ -422,13 +1582,6 @@ pub trait DebugDrawable {
     fn draw_debug(&mut self, r: &mut Renderer);
 }
 
-impl<'a> DebugDrawable for Game<'a> {
-    fn draw_debug(&mut self, r: &mut Renderer) {
-        self.camera.draw_debug(r);
-        self.player.draw_debug(r, &self.camera);
-    }
-}
-
 impl DebugDrawable for Camera {
     fn draw_debug(&mut self, r: &mut Renderer) {
         let rect = &self.collision_rect;
@@ -463,11 +1616,12 @@ impl CameraDebugDrawable for MoveableEntity {
 impl CameraDebugDrawable for Entity {
     fn draw_debug(&mut self, r: &mut Renderer, c: &Camera) {
         let rect = &self.collision_rect;
+        let (x, y) = c.world_to_screen(self.draw_pos(c.alpha));
         let draw_col = r.draw_color();
         r.set_draw_color(Color::RGB(255, 0, 0));
         r.draw_rect(Rect::new_unwrap(
-            rect.x() + self.pos.x as i32 - c.pos.x as i32,
-            rect.y() + self.pos.y as i32 - c.pos.y as i32,
+            rect.x() + x,
+            rect.y() + y,
             rect.width(),
             rect.height()
         ));
@@ -482,17 +1636,174 @@ pub trait Drawable {
     fn draw(&mut self, r: &mut Renderer);
 }
 
-impl<'a> Drawable for Game<'a> {
-    /// `Game`'s `draw` method calls the draw methods
-    /// for all entities that are currently onscreen.
+impl Drawable for Game {
+    /// `Game`'s `draw` method draws every pass in `DRAW_PASSES`
+    /// in turn, skipping `DrawPass::Debug` unless debugging.
+    /// While the camera is rotated, everything but the HUD is
+    /// drawn rotated, beneath the HUD.
     fn draw(&mut self, r: &mut Renderer) {
+        let camera = self.camera.interpolated();
+        if camera.rotation != 0.0 && self.draw_rotated(r, &camera) {
+            return;
+        }
+        self.draw_passes(r, &camera, &DRAW_PASSES);
+    }
+}
+
+impl Game {
+    fn draw_passes(&mut self, r: &mut Renderer, c: &Camera, passes: &[DrawPass]) {
+        for &pass in passes {
+            if pass == DrawPass::Debug && !self.debug {
+                continue;
+            }
+            self.draw_pass(r, c, pass);
+            self.systems.draw(pass, r, c);
+        }
+    }
+
+    /// Draw the world unrotated into a texture big enough to cover
+    /// the screen at any angle, then copy that to the screen turned
+    /// by `c.rotation`, and draw the HUD over it. Returns false,
+    /// having drawn nothing, if the renderer can't render to textures.
+    fn draw_rotated(&mut self, r: &mut Renderer, c: &Camera) -> bool {
+        let side = (c.width*c.width + c.height*c.height).sqrt().ceil();
+        let size = side as u32;
+        let stale = match self.rotated_view {
+            Some(ref tx) => {
+                let q = tx.query();
+                q.width != size || q.height != size
+            },
+            None => true,
+        };
+        if stale {
+            self.rotated_view = r.create_texture_target(PixelFormatEnum::RGBA8888, (size, size)).ok();
+        }
+        let tx = match self.rotated_view.take() {
+            Some(tx) => tx,
+            None => return false,
+        };
+        // drawing may already be redirected, e.g. by an EffectChain
+        let prev = match r.render_target().map(|mut rt| rt.set(tx)) {
+            Some(Ok(prev)) => prev,
+            _ => return false,
+        };
+
+        self.clear(r);
+        let margin = Vec2::new((side - c.width)/2.0, (side - c.height)/2.0);
+        let view = Camera {
+            pos: c.pos - margin,
+            width: side,
+            height: side,
+            rotation: 0.0,
+            ..*c
+        };
+        self.draw_passes(r, &view, &[
+            DrawPass::Background,
+            DrawPass::Map,
+            DrawPass::Decals,
+            DrawPass::Entities,
+            DrawPass::Foreground,
+            DrawPass::Debug,
+        ]);
+
+        let tx = match r.render_target() {
+            Some(mut rt) => match prev {
+                Some(prev) => rt.set(prev),
+                None => rt.reset(),
+            },
+            None => return false,
+        };
+        let tx = match tx {
+            Ok(Some(tx)) => tx,
+            _ => return false,
+        };
+        let (x, y) = (-margin).to_pixel();
+        r.copy_ex(&tx, None, Some(Rect::new_unwrap(x, y, size, size)), c.rotation, None, (false, false));
+        self.rotated_view = Some(tx);
+
+        self.draw_passes(r, c, &[DrawPass::Hud]);
+        true
+    }
+}
+
+impl PassDrawable for Game {
+    fn draw_pass(&mut self, r: &mut Renderer, c: &Camera, pass: DrawPass) {
         if let Some(ref mut map) = self.current_map {
-            map.draw(r, &self.camera);
+            map.draw_pass(r, c, pass);
         }
-        self.player.draw(r, &self.camera);
+        self.player.draw_pass(r, c, pass);
+        match pass {
+            DrawPass::Hud => {
+                self.draw_meters(r);
+                self.reticle.draw(r, c);
+            },
+            DrawPass::Debug => self.camera.draw_debug(r),
+            _ => (),
+        }
+    }
+}
+
+impl Game {
+    /// Draw a bar for each survival meter that isn't full,
+    /// in the top-left corner of the screen.
+    fn draw_meters(&self, r: &mut Renderer) {
+        const BAR_WIDTH: u32 = 100;
+        const BAR_HEIGHT: u32 = 10;
+        let draw_col = r.draw_color();
+        let mut y = 10;
+        for m in self.meters.meters.iter().filter(|m| !m.is_full()) {
+            let filled = (BAR_WIDTH as f64 * m.fraction()) as u32;
+            if filled > 0 {
+                r.set_draw_color(Color::RGB(80, 160, 255));
+                r.fill_rect(Rect::new_unwrap(10, y, filled, BAR_HEIGHT));
+            }
+            r.set_draw_color(Color::RGB(255, 255, 255));
+            r.draw_rect(Rect::new_unwrap(10, y, BAR_WIDTH, BAR_HEIGHT));
+            y += BAR_HEIGHT as i32 + 6;
+        }
+        r.set_draw_color(draw_col);
     }
 }
 
+/// The passes a frame is drawn in, back to front.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DrawPass {
+    /// The sky and parallax backgrounds.
+    Background,
+    /// Tile and image layers behind the entities.
+    Map,
+    /// Footprints, scorch marks and other decals stamped
+    /// onto the map.
+    Decals,
+    Entities,
+    /// Tile and image layers drawn over the entities.
+    Foreground,
+    /// Meters and other indicators, in screen space.
+    Hud,
+    /// Collision rects and other overlays, only drawn
+    /// when debugging.
+    Debug,
+}
+
+/// Every `DrawPass`, in the order they're drawn.
+pub const DRAW_PASSES: [DrawPass; 7] = [
+    DrawPass::Background,
+    DrawPass::Map,
+    DrawPass::Decals,
+    DrawPass::Entities,
+    DrawPass::Foreground,
+    DrawPass::Hud,
+    DrawPass::Debug,
+];
+
+/// The `PassDrawable` trait should be implemented by anything
+/// drawn in one or more `DrawPass`es. It's called once per pass
+/// per frame, and draws whatever belongs to `pass`, if anything.
+/// Nothing but the game loop presents the frame.
+pub trait PassDrawable {
+    fn draw_pass(&mut self, r: &mut Renderer, c: &Camera, pass: DrawPass);
+}
+
 /// The `CameraDrawable` trait should be implemented by
 /// anything that requires camera data during the
 /// rendering process.
@@ -505,17 +1816,16 @@ impl CameraDrawable for Entity {
         let (w, h) = if let Some(dr) = self.draw_rect {
             (dr.width(), dr.height())
         } else {
-            let q = self.sprite_map.query();
+            let q = self.sprite_map.borrow().query();
             (q.width, q.height)
         };
 
         // calculate screen x, y, using camera coordinates
-        let (screen_x, screen_y) = (
-            self.pos.x - c.pos.x,
-            self.pos.y - c.pos.y
-        );
-        r.copy(&self.sprite_map, self.draw_rect,
-            Rect::new(screen_x as i32, screen_y as i32, w, h).unwrap());
+        let (screen_x, screen_y) = c.world_to_screen(self.draw_pos(c.alpha));
+        let dst = Rect::new(screen_x, screen_y, w, h).unwrap();
+        let src = self.draw_rect;
+        self.copy_outline(r, src, dst);
+        self.copy_layers(r, src, dst, 255);
     }
 }
 
@@ -523,14 +1833,13 @@ impl CameraDrawable for MoveableEntity {
     fn draw(&mut self, r: &mut Renderer, c: &Camera) {
         if let (Some(dr), &Some(ref anim)) = (self.en.draw_rect, &self.anim) {
             // Calculate draw_rect
-            let off = anim.dir_to_offset.get(&self.dir).unwrap();
-            let frames = *anim.dir_to_frames.get(&self.dir).unwrap();
+            let (_, frames, off, dir_pos) = anim.frame_data(&self.dir);
             let sc = if anim.reverse && frames > 1 {
                 (frames - anim.sc) as u32
             } else {
                 anim.sc as u32
             };
-            let dir_pos = *anim.dir_to_pos.get(&self.dir).unwrap() as u32;
+            let dir_pos = dir_pos as u32;
             self.en.draw_rect = Some(Rect::new_unwrap(
                 (off.x as u32 + sc * dr.width()) as i32,
                 (off.y as u32 + dir_pos * dr.height()) as i32,
@@ -539,7 +1848,31 @@ impl CameraDrawable for MoveableEntity {
             ));
         }
 
-        self.en.draw(r, c);
+        let (x, y) = c.world_to_screen(self.en.draw_pos(c.alpha));
+        let aim = self.anim.as_ref().and_then(|a| a.aim_offset(&self.dir));
+        match (aim, self.en.draw_rect) {
+            (Some((off, split)), Some(dr)) if split > 0 && split < dr.height() => {
+                // upper body from the aim frame, legs from the current frame
+                let (upper_src, upper_dst) = (Rect::new(off.x as i32, off.y as i32, dr.width(), split).unwrap(),
+                    Rect::new(x, y, dr.width(), split).unwrap());
+                let (lower_src, lower_dst) = (Rect::new(dr.x(), dr.y() + split as i32, dr.width(), dr.height() - split).unwrap(),
+                    Rect::new(x, y + split as i32, dr.width(), dr.height() - split).unwrap());
+                // outline both halves first so neither covers the other
+                self.en.copy_outline(r, upper_src, upper_dst);
+                self.en.copy_outline(r, lower_src, lower_dst);
+                self.en.copy_layers(r, upper_src, upper_dst, 255);
+                self.en.copy_layers(r, lower_src, lower_dst, 255);
+            },
+            _ => self.en.draw(r, c),
+        }
+
+        // fade the outgoing frame out over the incoming one
+        if let Some(b) = self.anim.as_ref().and_then(|a| a.blend.as_ref()) {
+            let alpha = (255 * b.frames_left as u32 / (b.frames as u32 + 1)) as u8;
+            self.en.copy_layers(r, Some(b.from_rect),
+                Rect::new(x, y,
+                    b.from_rect.width(), b.from_rect.height()).unwrap(), alpha);
+        }
     }
 }
 
@@ -549,64 +1882,222 @@ impl CameraDrawable for Player {
     }
 }
 
+impl PassDrawable for Player {
+    fn draw_pass(&mut self, r: &mut Renderer, c: &Camera, pass: DrawPass) {
+        match pass {
+            DrawPass::Entities => self.draw(r, c),
+            DrawPass::Debug => self.draw_debug(r, c),
+            _ => (),
+        }
+    }
+}
+
 pub trait Updateable {
     fn update(&mut self);
 }
 
-impl<'a> Updateable for System<'a> {
-    fn update(&mut self) {
-        let _ = self.timer.recv();
-        self.fc += 1;
-        if self.fc > self.fps {
-            self.fc = 0;
+/// The `GameUpdateable` trait should be implemented by systems
+/// added to a `Registry`. They're given the game, to read and change
+/// as they like, and the length of a tick in milliseconds. Closures
+/// taking the same arguments implement it.
+pub trait GameUpdateable {
+    fn update(&mut self, game: &mut Game, dt: f64);
+}
+
+impl<F: FnMut(&mut Game, f64)> GameUpdateable for F {
+    fn update(&mut self, game: &mut Game, dt: f64) {
+        self(game, dt)
+    }
+}
+
+/// Where in a tick a registered `GameUpdateable` runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// After input is read, before the player and map move.
+    PrePhysics,
+    /// After everything has moved and the map's bounds
+    /// have been applied.
+    PostPhysics,
+}
+
+/// Custom systems a game adds to the loop without changing this
+/// crate. `GameUpdateable`s run once per tick at their `Stage`, and
+/// `CameraDrawable`s once per frame after the built-in drawing of
+/// their `DrawPass`, each in the order they were added. Anything
+/// meant to be drawn before the HUD goes in `DrawPass::Foreground`.
+pub struct Registry {
+    updateables: Vec<(Stage, Box<GameUpdateable>)>,
+    drawables: Vec<(DrawPass, Box<CameraDrawable>)>,
+    hooks: Vec<Box<FnMut(FrameHook, &FrameTimes)>>,
+    reporters: Vec<Box<FnMut(&Report)>>,
+}
+
+/// Points in a frame at which `Registry` frame hooks are called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameHook {
+    /// After waiting for the frame, before any ticks.
+    BeginFrame,
+    /// After the frame's ticks.
+    EndUpdate,
+    /// After the frame has been drawn and presented.
+    EndRender,
+}
+
+/// Problems `System` runs into while the game is running, passed
+/// to the `Registry` reporters.
+#[derive(Debug)]
+pub enum Report {
+    /// Frames in the last second in which each stage went over
+    /// its share of `budget`.
+    OverBudget { budget: FrameTimes, update: u32, render: u32 },
+    /// The current map couldn't be loaded again from its file.
+    ReloadFailed(PathBuf, tiled::ReadError),
+    /// The map a warp leads to couldn't be loaded.
+    WarpFailed(PathBuf, tiled::ReadError),
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Report::OverBudget { budget, update, render } => {
+                if update > 0 {
+                    try!(write!(f, "update went over its {:.1}ms budget in {} frames", budget.update, update));
+                }
+                if update > 0 && render > 0 {
+                    try!(write!(f, "; "));
+                }
+                if render > 0 {
+                    try!(write!(f, "render went over its {:.1}ms budget in {} frames", budget.render, render));
+                }
+                Ok(())
+            },
+            Report::ReloadFailed(ref path, ref e) => write!(f, "couldn't reload {}: {}", path.display(), e),
+            Report::WarpFailed(ref path, ref e) => write!(f, "couldn't warp to {}: {}", path.display(), e),
         }
+    }
+}
 
-        for event in self.ev_pump.poll_iter() {
-            match event {
-                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
-                    self.game.running = false
-                },
-                Event::KeyDown{keycode: Some(Keycode::Space), ..} => self.game.player.jump(),
-                _ => ()
-            }
+/// Wall time, in milliseconds, spent in each stage of a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimes {
+    /// Running the frame's simulation ticks.
+    pub update: f64,
+    /// Drawing, post-processing and presenting.
+    pub render: f64,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            updateables: vec!(),
+            drawables: vec!(),
+            hooks: vec!(),
+            reporters: vec!(),
         }
+    }
 
-        {
-            let me = &mut self.game.player.me;
-            const HORIZONTAL_ACCELERATION: f64 = 9.5;
-            if self.ev_pump.keyboard_state().is_scancode_pressed(Scancode::Left) {
-                me.a.x -= HORIZONTAL_ACCELERATION;
-                me.change_dir(Direction::Left);
-            } else if self.ev_pump.keyboard_state().is_scancode_pressed(Scancode::Right) {
-                me.a.x += HORIZONTAL_ACCELERATION;
-                me.change_dir(Direction::Right);
-            }
+    /// Add a hook called at every `FrameHook` with the
+    /// frame's `System::times` so far.
+    pub fn add_hook(&mut self, h: Box<FnMut(FrameHook, &FrameTimes)>) {
+        self.hooks.push(h);
+    }
+
+    /// Call every hook for `hook`.
+    pub fn frame_hook(&mut self, hook: FrameHook, times: &FrameTimes) {
+        for h in self.hooks.iter_mut() {
+            (*h)(hook, times);
         }
+    }
 
-        self.game.update();
-        if self.game.current_map.is_some() {
-            let (mut w, mut h) = (0, 0);
-            {
-                match self.game.current_map {
-                    Some(ref map) => {
-                        w = map.width*map.tile_width;
-                        h = map.height*map.tile_height;
-                    },
-                    None => {}
-                };
+    /// Add a reporter, called with every `Report` in place of
+    /// printing it to stderr.
+    pub fn add_reporter(&mut self, r: Box<FnMut(&Report)>) {
+        self.reporters.push(r);
+    }
+
+    /// Pass `report` to every reporter, or print it to stderr if
+    /// there are none.
+    pub fn report(&mut self, report: Report) {
+        if self.reporters.is_empty() {
+            eprintln!("{}", report);
+        }
+        for r in self.reporters.iter_mut() {
+            (*r)(&report);
+        }
+    }
+
+    pub fn add_updateable(&mut self, stage: Stage, u: Box<GameUpdateable>) {
+        self.updateables.push((stage, u));
+    }
+
+    pub fn add_drawable(&mut self, pass: DrawPass, d: Box<CameraDrawable>) {
+        self.drawables.push((pass, d));
+    }
+
+    /// Draw the `CameraDrawable`s added to `pass`.
+    pub fn draw(&mut self, pass: DrawPass, r: &mut Renderer, c: &Camera) {
+        for &mut (p, ref mut d) in self.drawables.iter_mut() {
+            if p == pass {
+                d.draw(r, c);
             }
-            self.game.keep_on_screen(w, h);
         }
     }
 }
 
-impl<'a> Updateable for Game<'a> {
+impl<'a> Updateable for System<'a> {
+    /// Wait for the next frame, then run however many simulation
+    /// ticks have come due since the last one.
+    fn update(&mut self) {
+        self.wait_for_frame();
+        let start = Instant::now();
+        self.record_frame(start);
+        self.game.systems.frame_hook(FrameHook::BeginFrame, &self.times);
+        self.accumulator += self.frame_time;
+        if self.accumulator > MAX_FRAME_TIME {
+            self.accumulator = MAX_FRAME_TIME;
+        }
+
+        let tick = 1000.0 / self.ups as f64;
+        while self.accumulator >= tick && self.game.running {
+            self.tick();
+            self.accumulator -= tick;
+        }
+        self.game.camera.alpha = self.accumulator / tick;
+
+        self.times.update = millis(start.elapsed());
+        if self.times.update > self.budget.update {
+            self.overruns.0 += 1;
+        }
+        self.game.systems.frame_hook(FrameHook::EndUpdate, &self.times);
+    }
+}
+
+impl Updateable for Game {
     fn update(&mut self) {
-        self.player.update();
+        self.player.me.en.prev_pos = self.player.me.en.pos;
+        self.camera.prev_pos = self.camera.pos;
+        if self.zip.is_some() {
+            self.update_zip_line();
+        } else {
+            if self.climbing {
+                self.update_climbing();
+            } else {
+                self.player.update();
+            }
+            if let Some(ref map) = self.current_map {
+                let from = self.player.me.en.prev_pos;
+                self.player.me.collide_with_map(map, from);
+            }
+        }
 
         if self.current_map.is_some() {
+            self.update_teleporters();
+            self.update_warps();
+            self.update_hazards();
+            self.update_meters();
             self.update_camera();
         }
+        self.update_aim();
     }
 }
 
@@ -626,8 +2117,18 @@ impl Updateable for MoveableEntity {
         }
 
         if let &mut Some(ref mut anim) = &mut self.anim {
-            let anim_len = *anim.dir_to_anim_len.get(&self.dir).unwrap();
-            let frame_count = *anim.dir_to_frames.get(&self.dir).unwrap();
+            anim.update_blend();
+
+            let idle = self.dir == Direction::StillLeft || self.dir == Direction::StillRight;
+            if anim.update_idle(idle) {
+                anim.sc = 1;
+                anim.ac = 0;
+            }
+
+            let (anim_len, frame_count) = {
+                let fd = anim.frame_data(&self.dir);
+                (fd.0, fd.1)
+            };
             let change_every = anim_len / frame_count;
             if anim.ac % change_every == 0 {
                 anim.sc += 1;
@@ -639,6 +2140,10 @@ impl Updateable for MoveableEntity {
             anim.ac += 1;
             if anim.ac > anim_len {
                 anim.ac = 1;
+                if anim.finish_idle_variation() {
+                    anim.sc = 1;
+                    anim.ac = 0;
+                }
             }
         }
     }
@@ -646,28 +2151,7 @@ impl Updateable for MoveableEntity {
 
 impl Updateable for Player {
     fn update(&mut self) {
-        const MOVEABLE_VELOCITY_DECAY_FACTOR_X: f64 = 0.2;
-        const MOVEABLE_VELOCITY_DECAY_FACTOR_Y: f64 = 0.7;
-        const MOVEABLE_VELOCITY_CUTOFF: f64 = 2.0;
-        const MOVEABLE_ACCELERATION_DECAY_FACTOR_X: f64 = 0.80;
-        const MOVEABLE_ACCELERATION_CUTOFF: f64 = 0.1;
-        self.me.a.y = 9.8;
-        self.me.en.pos.x += self.me.v.x as i64;
-        self.me.en.pos.y += self.me.v.y as i64;
-        self.me.v.x += self.me.a.x;
-        self.me.v.y += self.me.a.y;
-
-        self.me.v.x *= MOVEABLE_VELOCITY_DECAY_FACTOR_X;
-        self.me.v.y *= MOVEABLE_VELOCITY_DECAY_FACTOR_Y;
-        if self.me.v.x < MOVEABLE_VELOCITY_CUTOFF &&
-           self.me.v.x > -MOVEABLE_VELOCITY_CUTOFF { self.me.v.x = 0.0; }
-        if self.me.v.y < MOVEABLE_VELOCITY_CUTOFF &&
-           self.me.v.y > -MOVEABLE_VELOCITY_CUTOFF { self.me.v.y = 0.0; }
-
-        self.me.a.x *= MOVEABLE_ACCELERATION_DECAY_FACTOR_X;
-        if self.me.a.x < MOVEABLE_ACCELERATION_CUTOFF &&
-           self.me.a.x > -MOVEABLE_ACCELERATION_CUTOFF { self.me.a.x = 0.0; }
-
+        physics::step(&mut self.me.en.pos, &mut self.me.v, &mut self.me.a);
         self.me.update();
     }
 }
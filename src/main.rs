@@ -7,13 +7,16 @@ extern crate find_folder;
 extern crate platformer;
 
 use std::rc::Rc;
+use std::cell::RefCell;
 use platformer::*;
 use sdl2_image::{LoadTexture, INIT_PNG};
 use sdl2::rect::Rect;
+use sdl2::pixels::Color;
 
 fn main() {
     const TITLE: &'static str = "Platformer";
-    const FPS: u8 = 30;
+    const UPS: u8 = 30;
+    const FPS: u8 = 60;
     const WIDTH: u32 = 980;
     const HEIGHT: u32 = 700;
 
@@ -28,40 +31,40 @@ fn main() {
     let asset_path = find_folder::Search::ParentsThenKids(3, 3)
         .for_folder("assets")
         .unwrap();
-    let r = window.renderer().software().build().unwrap();
+    let r = window.renderer().software().target_texture().build().unwrap();
 
-    let map = match tiled::Map::read_json(asset_path.join("map2.json")) {
+    let map_path = asset_path.join("map2.json");
+    let map = match tiled::Map::read_json(&map_path) {
         Ok(m) => m,
-        Err(e) => match e {
-            tiled::ReadError::IoError(e) => panic!("IOError: {:?}", e),
-            tiled::ReadError::StringError(e) => panic!("StringError: {:?}", e),
-            tiled::ReadError::JsonError(e) => panic!("JSONError: {:?}", e),
-        },
+        Err(e) => panic!("couldn't read map: {}", e),
     };
 
-    let ts = map::Tileset::new_from_tiled_tileset(&asset_path.join("Platformer Pack/tiles_spritesheet.png"),
-        &map.tilesets[0], &r);
-    let mut new_map = map::Map::new_from_tiled_map(&map);
-    if let &Some(ref data) = &map.layers[0].data {
-        new_map.insert_data_using_tilset(data, &ts);
+    for d in map.validate(&map_path) {
+        eprintln!("{}", d);
     }
 
+    let new_map = match map::Map::load(&map_path, &r) {
+        Ok(m) => m,
+        Err(e) => panic!("couldn't load map: {}", e),
+    };
+
     let mut sys = System::new(
         Game::new(
             true,
             // false,
             None,
             Camera::new(
-                Point{x: 0, y: 0},
-                WIDTH as i64,
-                HEIGHT as i64,
-                Rect::new_unwrap(100, 100, 780, 500)
+                Vec2::zero(),
+                WIDTH as f64,
+                HEIGHT as f64,
+                physics::Rect::new(100, 100, 780, 500)
             ),
             Player::new(
-                Point{x: 250, y: 150},
-                Rect::new(10, 00, 32, 60).unwrap().unwrap(),
-                Rc::new(r.load_texture(&asset_path.join("sprite_map.png"))
-                             .unwrap()),
+                // placed at the map's player_start by set_map
+                Vec2::zero(),
+                physics::Rect::new(10, 0, 32, 60),
+                Rc::new(RefCell::new(r.load_texture(&asset_path.join("sprite_map.png"))
+                                         .unwrap())),
                 Rect::new(0, 0, 55, 65).unwrap(),
                 Direction::Right,
                 hashmap!(Direction::Up    => 1,
@@ -71,13 +74,13 @@ fn main() {
                          Direction::StillLeft => 4,
                          Direction::Right => 3,
                          Direction::StillRight => 3),
-                hashmap!(Direction::Up    => FPS,
-                         Direction::DoubleUp => FPS,
-                         Direction::Down  => FPS,
-                         Direction::Left  => FPS,
-                         Direction::StillLeft  => FPS,
-                         Direction::Right => FPS,
-                         Direction::StillRight => FPS),
+                hashmap!(Direction::Up    => UPS,
+                         Direction::DoubleUp => UPS,
+                         Direction::Down  => UPS,
+                         Direction::Left  => UPS,
+                         Direction::StillLeft  => UPS,
+                         Direction::Right => UPS,
+                         Direction::StillRight => UPS),
                 hashmap!(Direction::Up    => 1,
                          Direction::DoubleUp => 1,
                          Direction::Down  => 1,
@@ -85,34 +88,32 @@ fn main() {
                          Direction::StillLeft  => 1,
                          Direction::Right => 8,
                          Direction::StillRight => 1),
-                hashmap!(Direction::Up    => Point::origin(),
-                         Direction::DoubleUp => Point::origin(),
-                         Direction::Down  => Point::origin(),
-                         Direction::Left  => Point::origin(),
-                         Direction::StillLeft  => Point{x:55*3, y:0},
-                         Direction::Right => Point::origin(),
-                         Direction::StillRight => Point{x:55*3, y:0}),
+                hashmap!(Direction::Up    => Vec2::zero(),
+                         Direction::DoubleUp => Vec2::zero(),
+                         Direction::Down  => Vec2::zero(),
+                         Direction::Left  => Vec2::zero(),
+                         Direction::StillLeft  => Vec2::new(55.0*3.0, 0.0),
+                         Direction::Right => Vec2::zero(),
+                         Direction::StillRight => Vec2::new(55.0*3.0, 0.0)),
                 true
             )),
         r,
+        UPS,
         FPS,
         sdl_context.event_pump().unwrap(),
         &asset_path
     );
 
-    sys.game.set_map(&mut new_map);
+    sys.game.set_map(new_map);
 
-    // println!("{:?}", new_map.tiles.iter().map(|ref l| l.iter().map(|ref t| t.clip_rect).collect::<Vec<Option<Rect>>>()).collect::<Vec<Vec<Option<Rect>>>>());
+    sys.post.push("scanlines", Box::new(postprocess::Scanlines{spacing: 3, alpha: 60}), false);
+    sys.post.push("vignette", Box::new(postprocess::Vignette{width: 120, alpha: 160}), false);
+    sys.post.push("chromatic", Box::new(postprocess::ChromaticOffset{offset: 2, alpha: 90}), false);
+    sys.post.push(teleport::DEFAULT_EFFECT, Box::new(postprocess::Flash::new(Color::RGB(255, 255, 255), 10)), true);
 
     while sys.game.running {
         sys.update();
-        sys.game.clear(&mut sys.r);
-        //new_map.draw(&mut sys.r);
-        sys.game.draw(&mut sys.r);
-        if sys.game.debug {
-            sys.game.draw_debug(&mut sys.r);
-        }
-        sys.game.flip_buffer(&mut sys.r);
+        sys.render();
     }
 
     sdl2_image::quit();
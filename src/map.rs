@@ -1,29 +1,124 @@
 use std::rc::Rc;
-use std::path::Path;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::{HashMap, HashSet, VecDeque};
 use sdl2;
-use sdl2::rect::Rect;
-use sdl2::render::{Renderer, Texture};
+use sdl2::rect::{Rect, Point};
+use sdl2::render::{Renderer, Texture, BlendMode};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2_image::LoadTexture;
+use serde_json;
 use tiled;
-use super::{CameraDrawable, Camera};
+use collision::{self, CollisionMask, CollisionGrid};
+use physics;
+use teleport::{Teleporter, Warp};
+use grapple::{GrappleAnchor, ZipLine};
+use hazard::Hazard;
+use survival::MeterZone;
+use super::{CameraDrawable, PassDrawable, DrawPass, Camera, Direction, Vec2};
 
+/// Load the image at `path`, or an error naming it.
+fn load_image(path: &Path, r: &Renderer) -> Result<Texture, tiled::ReadError> {
+    r.load_texture(path).map_err(|e| tiled::ReadError::ImageError(e.to_string()).in_file(path))
+}
+
+#[derive(Clone)]
 pub struct Tileset {
     pub firstgid: u32,
-    pub texture: Rc<Texture>,
+    pub texture: Rc<RefCell<Texture>>,
     pub texture_width: u32,
     pub texture_height: u32,
     pub tile_width: u32,
     pub tile_height: u32,
     pub tile_count: u32,
+    /// Number of tiles in a row of the image.
+    pub columns: u32,
     pub margin: u32,
     pub spacing: u32,
+    /// A `HashMap` from a tile's local id to its animation frames,
+    /// each a local tile id and a duration in milliseconds.
+    pub animations: HashMap<u32, Vec<(u32, u32)>>,
+    /// A `HashMap` from a tile's local id to the collision
+    /// shapes drawn for it in Tiled.
+    pub shapes: HashMap<u32, Vec<TileShape>>,
+    /// Local ids of tiles whose `solid` property is false, which
+    /// are drawn but never collided with.
+    pub passable: HashSet<u32>,
+    /// A `HashMap` from a tile's local id to its custom properties,
+    /// for tiles that have any.
+    pub properties: HashMap<u32, tiled::Properties>,
+    /// A `HashMap` from a tile's name to its local id, for tiles
+    /// with a `name` property or, failing that, a class.
+    pub names: HashMap<String, u32>,
+    /// Terrain transition data, for auto-tiling. Terrains from
+    /// maps made before Tiled 1.5 are converted to a corner set.
+    pub wangsets: Vec<tiled::WangSet>,
+    /// Clip rect of each tile, indexed by local id.
+    clip_rects: Vec<Rect>,
+}
+
+/// A collision shape, in pixels.
+#[derive(Clone, Debug)]
+pub enum TileShape {
+    Rect { x: f64, y: f64, width: f64, height: f64 },
+    Polygon(Vec<(f64, f64)>),
+}
+
+impl TileShape {
+    /// Returns the shape of a collision editor object,
+    /// if it's one we can collide with.
+    fn from_object(o: &tiled::Object) -> Option<Self> {
+        match o.shape {
+            tiled::Shape::Polygon(_) => Some(TileShape::Polygon(o.vertices().iter().map(|v| (v.x, v.y)).collect())),
+            tiled::Shape::Rectangle | tiled::Shape::Ellipse if o.width > 0.0 && o.height > 0.0 =>
+                Some(TileShape::Rect { x: o.x, y: o.y, width: o.width, height: o.height }),
+            _ => None,
+        }
+    }
+
+    /// Returns the shape moved by `dx`, `dy`.
+    pub fn translate(&self, dx: f64, dy: f64) -> Self {
+        match *self {
+            TileShape::Rect { x, y, width, height } =>
+                TileShape::Rect { x: x + dx, y: y + dy, width: width, height: height },
+            TileShape::Polygon(ref vs) =>
+                TileShape::Polygon(vs.iter().map(|&(x, y)| (x + dx, y + dy)).collect()),
+        }
+    }
 }
 
 impl Tileset {
-    pub fn new_from_tiled_tileset(img_path: &Path, ts: &tiled::Tileset, r: &Renderer) -> Self {
-        let tx = Rc::new(r.load_texture(img_path).ok().expect("couldn't load tileset image"));
+    pub fn new_from_tiled_tileset(img_path: &Path, ts: &tiled::Tileset, r: &Renderer) -> Result<Self, tiled::ReadError> {
+        let tx = try!(load_image(img_path, r));
         let sdl2::render::TextureQuery{width: w, height: h, ..} = tx.query();
-        Tileset {
+        let tx = Rc::new(RefCell::new(tx));
+        let (mut animations, mut shapes) = (HashMap::new(), HashMap::new());
+        let (mut passable, mut properties) = (HashSet::new(), HashMap::new());
+        let mut names = HashMap::new();
+        if let Some(ref tiles) = ts.tiles {
+            for t in tiles {
+                match t.properties.get_str("name") {
+                    Some(name) if !name.is_empty() => { names.insert(name.to_string(), t.id); },
+                    _ if !t.tile_type.is_empty() => { names.insert(t.tile_type.clone(), t.id); },
+                    _ => (),
+                }
+                if !t.properties.is_empty() {
+                    properties.insert(t.id, t.properties.clone());
+                }
+                if t.properties.get_bool("solid") == Some(false) {
+                    passable.insert(t.id);
+                }
+                if let Some(ref frames) = t.animation {
+                    animations.insert(t.id, frames.iter().map(|f| (f.tileid, f.duration)).collect());
+                }
+                if let Some(tiled::Layer{kind: tiled::LayerKind::Objects(ref objects), ..}) = t.objectgroup {
+                    shapes.insert(t.id, objects.iter().filter_map(TileShape::from_object).collect());
+                }
+            }
+        }
+        let mut tileset = Tileset {
             firstgid: ts.firstgid,
             texture: tx,
             texture_width: w,
@@ -31,121 +126,1966 @@ impl Tileset {
             tile_width: ts.tilewidth,
             tile_height: ts.tileheight,
             tile_count: ts.tilecount,
+            columns: match ts.columns {
+                0 => tiled::columns_in_image(w, ts.tilewidth, ts.margin, ts.spacing),
+                c => c,
+            },
             margin: ts.margin,
             spacing: ts.spacing,
-        }
+            animations: animations,
+            shapes: shapes,
+            passable: passable,
+            properties: properties,
+            names: names,
+            wangsets: Tileset::wangsets_from_tiled(ts),
+            clip_rects: vec!(),
+        };
+        let clip_rects = (0..tileset.tile_count).map(|id| tileset.compute_clip_rect(id)).collect();
+        tileset.clip_rects = clip_rects;
+        Ok(tileset)
     }
 
-    pub fn side_len(&self) -> u32 {
-        return (self.tile_count as f64).sqrt() as u32;
+    fn wangsets_from_tiled(ts: &tiled::Tileset) -> Vec<tiled::WangSet> {
+        let mut wangsets = ts.wangsets.clone().unwrap_or(vec!());
+        if let (&Some(ref terrains), &Some(ref tiles)) = (&ts.terrains, &ts.tiles) {
+            // wangids go clockwise from the top edge, so the corners
+            // are at 1 (top-right), 3, 5 and 7 (top-left)
+            let wangtiles = tiles.iter().filter_map(|t| t.terrain.as_ref().map(|c| {
+                let color = |i: usize| c.get(i).map_or(0, |&t| (t + 1) as u8);
+                tiled::WangTile {
+                    tileid: t.id,
+                    wangid: vec!(0, color(1), 0, color(3), 0, color(2), 0, color(0)),
+                }
+            })).collect();
+            wangsets.push(tiled::WangSet {
+                name: "terrains".to_string(),
+                wang_type: "corner".to_string(),
+                tile: -1,
+                colors: terrains.iter().map(|t| tiled::WangColor {
+                    name: t.name.clone(),
+                    color: tiled::Color { r: 0, g: 0, b: 0, a: 255 },
+                    tile: t.tile,
+                    probability: 1.0,
+                }).collect(),
+                wangtiles: wangtiles,
+                properties: tiled::Properties::default(),
+            });
+        }
+        wangsets
     }
 
     fn row_for_id(&self, id: u32) -> u32 {
-        return id / self.side_len();
+        return id / self.columns.max(1);
     }
 
     fn col_for_id(&self, id: u32) -> u32 {
-        return id % self.side_len();
+        return id % self.columns.max(1);
     }
 
-    pub fn tile_for_id(&self, mut id: u32) -> Option<Rect> {
-        if id == 0 {
-            return None;
-        }
-        id -= 1;
-
+    fn compute_clip_rect(&self, id: u32) -> Rect {
         let (mut x, mut y) = (self.margin, self.margin);
         let (row, col) = (self.row_for_id(id), self.col_for_id(id));
 
         x += col * (self.tile_width + self.spacing);
         y += row * (self.tile_height + self.spacing);
 
-        Some(Rect::new_unwrap(x as i32,  y as i32, self.tile_width, self.tile_height))
+        Rect::new_unwrap(x as i32,  y as i32, self.tile_width, self.tile_height)
+    }
+
+    /// Returns the clip rect of the tile with GID `id`, or `None`
+    /// if it's empty or not in this tileset.
+    pub fn tile_for_id(&self, id: u32) -> Option<Rect> {
+        if id == 0 || id < self.firstgid {
+            return None;
+        }
+        self.clip_rects.get((id - self.firstgid) as usize).cloned()
+    }
+
+    /// Returns the GID of the tile called `name`, if there is one.
+    /// If several tiles have the same name, the last one wins.
+    pub fn gid_for(&self, name: &str) -> Option<u32> {
+        self.names.get(name).map(|&id| id + self.firstgid)
+    }
+
+    /// Returns the clip rect of the tile called `name`, for
+    /// drawing it as a sprite straight from the tileset.
+    pub fn rect_for(&self, name: &str) -> Option<Rect> {
+        self.gid_for(name).and_then(|gid| self.tile_for_id(gid))
+    }
+
+    /// Returns a fresh `TileAnimation` for the tile `id`, if the
+    /// tileset defines one for it.
+    pub fn animation_for_id(&self, id: u32) -> Option<TileAnimation> {
+        if id < self.firstgid {
+            return None;
+        }
+        self.animations.get(&(id - self.firstgid)).map(|frames| {
+            TileAnimation::new(frames.iter()
+                .map(|&(tileid, duration)| (self.tile_for_id(tileid + self.firstgid), duration))
+                .collect())
+        })
     }
 }
 
-pub struct Tile {
-    pub texture: Rc<Texture>,
-    pub clip_rect: Option<Rect>,
+/// The playback state of an animated tile. All tiles sharing
+/// an id share a `TileAnimation`, so they animate in step.
+#[derive(Clone)]
+pub struct TileAnimation {
+    /// Each frame's clip rect and duration in milliseconds.
+    pub frames: Vec<(Option<Rect>, u32)>,
+    pub current: usize,
+    /// Milliseconds spent on the current frame.
+    pub elapsed: u32,
 }
 
-impl Tile {
-    pub fn new(tx: Rc<Texture>, cr: Option<Rect>) -> Self {
-        Tile {
-            texture: tx,
-            clip_rect: cr,
+impl TileAnimation {
+    pub fn new(frames: Vec<(Option<Rect>, u32)>) -> Self {
+        TileAnimation {
+            frames: frames,
+            current: 0,
+            elapsed: 0,
+        }
+    }
+
+    /// Advance the animation by `dt` milliseconds.
+    pub fn update(&mut self, dt: u32) {
+        if self.frames.is_empty() {
+            return;
+        }
+        self.elapsed += dt;
+        while self.frames[self.current].1 > 0 && self.elapsed >= self.frames[self.current].1 {
+            self.elapsed -= self.frames[self.current].1;
+            self.current = (self.current + 1) % self.frames.len();
+        }
+    }
+
+    pub fn clip_rect(&self) -> Option<Rect> {
+        match self.frames.get(self.current) {
+            Some(&(cr, _)) => cr,
+            None => None,
         }
     }
 }
 
-impl Clone for Tile {
-    fn clone(&self) -> Self {
-        Tile {
-            texture: self.texture.clone(),
-            clip_rect: self.clip_rect.clone(),
+/// A named grid of tiles, drawn in the order it was added to a `Map`.
+#[derive(Clone)]
+pub struct TileLayer {
+    pub name: String,
+    /// The GID of every tile, row by row. 0 is empty.
+    pub gids: Vec<u32>,
+    /// Offset of the layer from the map's origin, in pixels.
+    pub offset_x: i64,
+    pub offset_y: i64,
+    /// How fast the layer scrolls relative to the camera.
+    pub parallax_x: f64,
+    pub parallax_y: f64,
+    pub visible: bool,
+    pub opacity: f64,
+    /// Color the layer's tiles are multiplied with.
+    pub tint: Option<tiled::Color>,
+    /// Whether the layer's tiles are solid.
+    pub collides: bool,
+    /// Whether this is the map's collision layer, which is never
+    /// drawn and, if present, is the only source of solid tiles.
+    pub collision: bool,
+    /// Whether the layer is drawn over entities, by `draw_foreground`,
+    /// rather than behind them.
+    pub foreground: bool,
+    /// Pre-rendered chunks of the layer's static tiles, row-major,
+    /// `None` until drawn or after their tiles change.
+    cache: Vec<Option<Rc<RefCell<Texture>>>>,
+}
+
+impl TileLayer {
+    fn new(name: &str, gids: Vec<u32>) -> Self {
+        TileLayer {
+            name: name.to_string(),
+            gids: gids,
+            offset_x: 0,
+            offset_y: 0,
+            parallax_x: 1.0,
+            parallax_y: 1.0,
+            visible: true,
+            opacity: 1.0,
+            tint: None,
+            collides: true,
+            collision: false,
+            foreground: false,
+            cache: vec!(),
         }
     }
 }
 
+/// Side length, in tiles, of the chunks static tiles are cached in.
+pub const CACHE_CHUNK_SIZE: u32 = 16;
+
+/// Name of the layer used as a map's collision layer.
+pub const COLLISION_LAYER: &'static str = "collision";
+
+/// Returns the top-left of the view of a layer with the given
+/// offset and parallax factors, in the layer's own coordinates.
+fn layer_view(c: &Camera, offset: (i64, i64), parallax: (f64, f64)) -> (i64, i64) {
+    ((c.pos.x * parallax.0) as i64 - offset.0, (c.pos.y * parallax.1) as i64 - offset.1)
+}
+
+/// Set the alpha and color mods of `tx` for drawing a layer
+/// with the given `opacity` and `tint`.
+fn set_layer_mods(tx: &mut Texture, opacity: f64, tint: Option<tiled::Color>) {
+    let (r, g, b, a) = match tint {
+        Some(c) => (c.r, c.g, c.b, c.a),
+        None => (255, 255, 255, 255),
+    };
+    tx.set_alpha_mod((opacity * a as f64) as u8);
+    tx.set_color_mod(r, g, b);
+}
+
 #[derive(Clone)]
 pub struct Map {
     pub width: u32,
     pub height: u32,
     pub tile_width: u32,
     pub tile_height: u32,
-    pub tiles: Vec<Vec<Tile>>,
+    pub orientation: tiled::Orientation,
+    /// Length of a hexagon's flat side, for hexagonal maps.
+    pub hex_side_length: u32,
+    /// Whether staggered and hexagonal maps shift every other
+    /// column (rather than row), and whether it's the odd ones.
+    pub stagger_x: bool,
+    pub stagger_odd: bool,
+    pub layers: Vec<TileLayer>,
+    /// Which tiles are solid: any tile with a solid tile in at
+    /// least one colliding layer, or with any tile in the
+    /// collision layer if there is one.
+    pub collision: CollisionMask,
+    /// Color the screen is cleared to before the map is drawn.
+    pub background_color: Option<tiled::Color>,
+    /// Gradient and image drawn behind the parallax background.
+    pub sky: Sky,
+    /// Parallax images drawn behind the image and tile layers.
+    pub background: Background,
+    /// Images drawn between the tile layers, in order.
+    pub image_layers: Vec<ImageLayer>,
+    /// The tilesets used by the tile layers, sorted by `firstgid`.
+    /// Each GID belongs to the last tileset whose `firstgid` is no
+    /// greater than it.
+    pub tilesets: Vec<Tileset>,
+    /// Column and row, in Tiled's coordinates, of each layer's
+    /// `tiles[0][0]`. Only non-zero for infinite maps, whose chunks
+    /// can start at negative coordinates.
+    pub origin_col: i32,
+    pub origin_row: i32,
+    /// A `HashMap` from GID to the animation shared by
+    /// every tile with that GID.
+    pub tile_animations: HashMap<u32, TileAnimation>,
+    /// Temporary marks drawn over the tiles.
+    pub decals: Decals,
+    /// What happens to entities that leave the map.
+    pub bounds: Bounds,
+    pub teleporters: Vec<Teleporter>,
+    pub warps: Vec<Warp>,
+    pub grapple_anchors: Vec<GrappleAnchor>,
+    pub zip_lines: Vec<ZipLine>,
+    pub hazards: Vec<Hazard>,
+    pub meter_zones: Vec<MeterZone>,
+    /// Milliseconds the map has been updated for, which
+    /// moving hazards are timed by.
+    pub elapsed: u32,
+    pub player_start: Option<PlayerStart>,
+    /// Every named `player_start`, by name, for warps to arrive at.
+    pub spawn_points: HashMap<String, PlayerStart>,
+    /// The file the map was loaded from, which warp paths
+    /// are relative to.
+    pub path: Option<PathBuf>,
+    /// Whether to draw static tiles of orthogonal maps from cached
+    /// chunk textures. Cleared if the renderer can't render to
+    /// textures.
+    pub cache_tiles: bool,
+}
+
+/// What happens when an entity crosses an edge of the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    /// The entity is stopped at the edge.
+    Block,
+    /// The entity reappears at the opposite edge.
+    Wrap,
+    /// The entity may leave the map.
+    Open,
+}
+
+impl Edge {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "block" => Some(Edge::Block),
+            "wrap" => Some(Edge::Wrap),
+            "open" => Some(Edge::Open),
+            _ => None,
+        }
+    }
+}
+
+/// A map's out-of-bounds policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    /// Policy for the left and right edges.
+    pub x: Edge,
+    /// Policy for the top and bottom edges. The bottom edge
+    /// never blocks when there's a kill plane.
+    pub y: Edge,
+    /// Entities whose top is below this world y coordinate die.
+    pub kill_below: Option<i64>,
+}
+
+impl Bounds {
+    /// Read the policy from the map properties `bounds_x` and
+    /// `bounds_y` (`block`, `wrap` or `open`, defaulting to
+    /// `block`) and `kill_below` (pixels).
+    pub fn from_properties(p: &tiled::Properties) -> Self {
+        let edge = |name| p.get_str(name).and_then(Edge::parse).unwrap_or(Edge::Block);
+        Bounds {
+            x: edge("bounds_x"),
+            y: edge("bounds_y"),
+            kill_below: p.get_int("kill_below"),
+        }
+    }
+}
+
+/// Object type of the player's starting point in Tiled object layers.
+pub const PLAYER_START_TYPE: &'static str = "player_start";
+
+/// Where the player starts a map, from a `player_start` object. The
+/// player stands centred on the bottom of the object (or on it, for
+/// a point). Its `facing` property is `left` or `right`, and its
+/// `camera_x` and `camera_y` properties place the camera's top-left
+/// corner; otherwise the camera is centred on the player.
+#[derive(Clone, Debug)]
+pub struct PlayerStart {
+    /// Where the player's feet go, in world pixels.
+    pub pos: Vec2,
+    pub facing: Direction,
+    pub camera: Option<Vec2>,
+}
+
+impl PlayerStart {
+    /// Build a `PlayerStart` from `o`, which is in a layer
+    /// offset by `offset` pixels.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Self {
+        let p = &o.properties;
+        let facing = match p.get_str("facing") {
+            Some("left") => Direction::StillLeft,
+            _ => Direction::StillRight,
+        };
+        let camera = match (p.get_float("camera_x"), p.get_float("camera_y")) {
+            (Some(x), Some(y)) => Some(Vec2::new(x, y)),
+            _ => None,
+        };
+        PlayerStart {
+            pos: Vec2::new(o.x + o.width/2.0 + offset.0, o.y + o.height + offset.1),
+            facing: facing,
+            camera: camera,
+        }
+    }
+
+    /// The first `player_start` object in `layers`, which
+    /// should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Option<Self> {
+        for l in layers {
+            if let tiled::LayerKind::Objects(ref objects) = l.kind {
+                if let Some(o) = objects.iter().find(|o| o.object_type == PLAYER_START_TYPE) {
+                    return Some(PlayerStart::from_object(o, (l.offsetx, l.offsety)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Every `player_start` object in `layers`, which should be
+    /// flattened, that has a name, by name.
+    pub fn named_from_layers(layers: &[tiled::Layer]) -> HashMap<String, Self> {
+        let mut starts = HashMap::new();
+        for l in layers {
+            if let tiled::LayerKind::Objects(ref objects) = l.kind {
+                for o in objects.iter().filter(|o| o.object_type == PLAYER_START_TYPE && !o.name.is_empty()) {
+                    starts.insert(o.name.clone(), PlayerStart::from_object(o, (l.offsetx, l.offsety)));
+                }
+            }
+        }
+        starts
+    }
+}
+
+/// Returns the first column and row of `chunks` and the column and
+/// row just past their last, or `None` if there are no chunks.
+fn chunk_bounds(chunks: &[tiled::Chunk]) -> Option<(i32, i32, i32, i32)> {
+    if chunks.is_empty() {
+        return None;
+    }
+    Some((chunks.iter().map(|c| c.x).min().unwrap(),
+          chunks.iter().map(|c| c.y).min().unwrap(),
+          chunks.iter().map(|c| c.x + c.width as i32).max().unwrap(),
+          chunks.iter().map(|c| c.y + c.height as i32).max().unwrap()))
+}
+
+/// The union of bounds `a` and `b`, as returned by `chunk_bounds`.
+fn union_bounds(a: (i32, i32, i32, i32), b: (i32, i32, i32, i32)) -> (i32, i32, i32, i32) {
+    (::std::cmp::min(a.0, b.0), ::std::cmp::min(a.1, b.1),
+     ::std::cmp::max(a.2, b.2), ::std::cmp::max(a.3, b.3))
 }
 
 impl Map {
     pub fn new_from_tiled_map(tmap: &tiled::Map) -> Self {
+        // an infinite map covers every chunk of every layer, which
+        // needn't line up with its width and height
+        let bounds = tmap.flattened_layers().iter()
+            .filter_map(|l| match l.kind {
+                tiled::LayerKind::Chunks(ref chunks) => chunk_bounds(chunks),
+                _ => None,
+            })
+            .fold(None, |all, b| Some(all.map_or(b, |all| union_bounds(all, b))));
+        let (origin_col, origin_row, width, height) = match bounds {
+            Some((c0, r0, c1, r1)) => (c0, r0, (c1 - c0) as u32, (r1 - r0) as u32),
+            None => (0, 0, tmap.width, tmap.height),
+        };
         Map {
-            width: tmap.width,
-            height: tmap.height,
+            width: width,
+            height: height,
             tile_width: tmap.tilewidth,
             tile_height: tmap.tileheight,
-            tiles: Vec::new(),
+            orientation: tmap.orientation,
+            hex_side_length: tmap.hexsidelength.unwrap_or(0),
+            stagger_x: tmap.staggeraxis.as_ref().map_or(false, |a| a == "x"),
+            stagger_odd: tmap.staggerindex.as_ref().map_or(true, |i| i == "odd"),
+            layers: Vec::new(),
+            collision: CollisionMask::new(width, height),
+            background_color: tmap.backgroundcolor,
+            sky: Sky::from_properties(tmap.backgroundcolor, &tmap.properties),
+            background: Background::new(),
+            image_layers: Vec::new(),
+            tilesets: Vec::new(),
+            origin_col: origin_col,
+            origin_row: origin_row,
+            tile_animations: HashMap::new(),
+            decals: Decals::new(DEFAULT_DECAL_CAPACITY),
+            bounds: Bounds::from_properties(&tmap.properties),
+            teleporters: Teleporter::from_layers(&tmap.flattened_layers()),
+            warps: Warp::from_layers(&tmap.flattened_layers()),
+            grapple_anchors: GrappleAnchor::from_layers(&tmap.flattened_layers()),
+            zip_lines: ZipLine::from_layers(&tmap.flattened_layers()),
+            hazards: Hazard::from_layers(&tmap.flattened_layers()),
+            meter_zones: MeterZone::from_layers(&tmap.flattened_layers()),
+            elapsed: 0,
+            player_start: PlayerStart::from_layers(&tmap.flattened_layers()),
+            spawn_points: PlayerStart::named_from_layers(&tmap.flattened_layers()),
+            path: None,
+            cache_tiles: true,
+        }
+    }
+
+    /// Read the map at `path`, in either format, and load its tilesets
+    /// and layers, ready to draw.
+    pub fn load(path: &Path, r: &Renderer) -> Result<Self, tiled::ReadError> {
+        let tmap = try!(tiled::Map::read(path));
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut map = Map::new_from_tiled_map(&tmap);
+        for ts in &tmap.tilesets {
+            map.add_tileset(&try!(Tileset::new_from_tiled_tileset(&dir.join(&ts.image), ts, r)));
+        }
+        for l in &tmap.flattened_layers() {
+            match l.kind {
+                tiled::LayerKind::Image(ref il) if !il.image.is_empty() => {
+                    map.add_image_layer(try!(ImageLayer::new_from_tiled_layer(&dir.join(&il.image), l, il, r)));
+                },
+                _ => map.insert_tiled_layer(l),
+            }
+        }
+        if let Some(img) = tmap.properties.get_str("sky_image") {
+            map.sky.texture = Some(Rc::new(RefCell::new(try!(load_image(&dir.join(img), r)))));
+        }
+        map.path = Some(path.to_path_buf());
+        Ok(map)
+    }
+
+    /// Where the player arrives from a warp to `target`: the
+    /// spawn point of that name, or else the `player_start`.
+    pub fn spawn_point(&self, target: Option<&str>) -> Option<&PlayerStart> {
+        target.and_then(|t| self.spawn_points.get(t)).or(self.player_start.as_ref())
+    }
+
+    /// Path of the map `warp` leads to.
+    pub fn warp_path(&self, warp: &Warp) -> PathBuf {
+        match self.path {
+            Some(ref p) => p.parent().unwrap_or(Path::new("")).join(&warp.map),
+            None => PathBuf::from(&warp.map),
+        }
+    }
+
+    /// The color to clear the screen to before drawing the
+    /// map: its background color, or black if it has none.
+    pub fn clear_color(&self) -> Color {
+        match self.background_color {
+            Some(c) => Color::RGBA(c.r, c.g, c.b, c.a),
+            None => Color::RGB(0, 0, 0),
         }
     }
 
     pub fn pixel_width(&self) -> u32 {
-        return self.width*self.tile_width;
+        let (w, h, tw) = (self.width, self.height, self.tile_width);
+        match self.orientation {
+            tiled::Orientation::Orthogonal => return w*tw,
+            tiled::Orientation::Isometric => return (w + h)*tw/2,
+            _ if self.stagger_x => return w.saturating_sub(1)*self.stagger_step() as u32 + tw,
+            _ => return w*tw + if h > 1 { tw/2 } else { 0 },
+        }
     }
 
     pub fn pixel_height(&self) -> u32 {
-        return self.height*self.tile_height;
+        let (w, h, th) = (self.width, self.height, self.tile_height);
+        match self.orientation {
+            tiled::Orientation::Orthogonal => return h*th,
+            tiled::Orientation::Isometric => return (w + h)*th/2,
+            _ if self.stagger_x => return h*th + if w > 1 { th/2 } else { 0 },
+            _ => return h.saturating_sub(1)*self.stagger_step() as u32 + th,
+        }
+    }
+
+    /// Distance between consecutive staggered rows, or columns
+    /// if `stagger_x`, of a staggered or hexagonal map.
+    fn stagger_step(&self) -> i32 {
+        let side = match self.orientation {
+            tiled::Orientation::Hexagonal => self.hex_side_length as i32,
+            _ => 0,
+        };
+        if self.stagger_x {
+            return (self.tile_width as i32 + side) / 2;
+        }
+        return (self.tile_height as i32 + side) / 2;
+    }
+
+    /// Whether row (or column, if `stagger_x`) `index` of a staggered
+    /// or hexagonal map is shifted by half a tile.
+    fn is_staggered(&self, index: i32) -> bool {
+        return (index & 1 == 1) == self.stagger_odd;
+    }
+
+    /// Returns the top-left corner, in world pixels, of the cell
+    /// the tile at `col`, `row` is drawn in.
+    pub fn tile_to_pixel(&self, col: i32, row: i32) -> (i32, i32) {
+        let (tw, th) = (self.tile_width as i32, self.tile_height as i32);
+        match self.orientation {
+            tiled::Orientation::Orthogonal => return (col*tw, row*th),
+            // shifted right so the left corner of the diamond is at 0
+            tiled::Orientation::Isometric =>
+                return ((col - row + self.height as i32 - 1)*tw/2, (col + row)*th/2),
+            _ if self.stagger_x => {
+                let shift = if self.is_staggered(col) { th/2 } else { 0 };
+                return (col*self.stagger_step(), row*th + shift);
+            },
+            _ => {
+                let shift = if self.is_staggered(row) { tw/2 } else { 0 };
+                return (col*tw + shift, row*self.stagger_step());
+            },
+        }
+    }
+
+    /// Returns the column and row of the tile at world pixel `x`, `y`.
+    /// The result may be outside the map.
+    pub fn pixel_to_tile(&self, x: i32, y: i32) -> (i32, i32) {
+        let (tw, th) = (self.tile_width as f64, self.tile_height as f64);
+        let (fx, fy) = (x as f64, y as f64);
+        match self.orientation {
+            tiled::Orientation::Orthogonal => return ((fx / tw).floor() as i32, (fy / th).floor() as i32),
+            tiled::Orientation::Isometric => {
+                // in half-tile units, from the top corner of tile (0, 0)
+                let u = (fx - (self.height as f64 - 1.0)*tw/2.0 - tw/2.0) / (tw/2.0);
+                let v = fy / (th/2.0);
+                return (((u + v)/2.0).floor() as i32, ((v - u)/2.0).floor() as i32);
+            },
+            _ => {
+                // pick the nearest tile centre around a rough estimate
+                let step = self.stagger_step() as f64;
+                let (col, row) = if self.stagger_x {
+                    ((fx / step).floor() as i32, (fy / th).floor() as i32)
+                } else {
+                    ((fx / tw).floor() as i32, (fy / step).floor() as i32)
+                };
+                let mut best = (col, row, ::std::f64::MAX);
+                for r in row-1..row+2 {
+                    for c in col-1..col+2 {
+                        let (cx, cy) = self.tile_to_pixel(c, r);
+                        let (dx, dy) = ((fx - cx as f64 - tw/2.0) / tw, (fy - cy as f64 - th/2.0) / th);
+                        let d = match self.orientation {
+                            tiled::Orientation::Staggered => dx.abs() + dy.abs(),
+                            _ => dx*dx + dy*dy,
+                        };
+                        if d < best.2 {
+                            best = (c, r, d);
+                        }
+                    }
+                }
+                return (best.0, best.1);
+            },
+        }
+    }
+
+    /// Add `ts` to the tilesets GIDs are resolved with, replacing
+    /// any tileset with the same `firstgid`.
+    pub fn add_tileset(&mut self, ts: &Tileset) {
+        match self.tilesets.iter().position(|t| t.firstgid >= ts.firstgid) {
+            Some(i) if self.tilesets[i].firstgid == ts.firstgid => self.tilesets[i] = ts.clone(),
+            Some(i) => self.tilesets.insert(i, ts.clone()),
+            None => self.tilesets.push(ts.clone()),
+        }
+
+        // tiles already placed may have been waiting on this tileset
+        let gids: Vec<u32> = self.layers.iter().flat_map(|l| l.gids.iter().cloned()).collect();
+        for gid in gids {
+            self.add_animation(gid);
+        }
+        self.rebuild_collision();
+        self.invalidate_cache();
+    }
+
+    /// Returns the tileset `gid` belongs to.
+    pub fn tileset_for_gid(&self, gid: u32) -> Option<&Tileset> {
+        if gid == 0 {
+            return None;
+        }
+        self.tilesets.iter().rev().find(|t| t.firstgid <= gid)
+    }
+
+    /// Returns the clip rect of `gid` in its tileset.
+    pub fn tile_for_gid(&self, gid: u32) -> Option<Rect> {
+        self.tileset_for_gid(gid).and_then(|ts| ts.tile_for_id(gid))
+    }
+
+    /// Returns the Wang set called `name` and the tileset it's in.
+    pub fn wangset(&self, name: &str) -> Option<(&Tileset, &tiled::WangSet)> {
+        self.tilesets.iter()
+            .filter_map(|ts| ts.wangsets.iter().find(|w| w.name == name).map(|w| (ts, w)))
+            .next()
+    }
+
+    /// Returns the collision shapes drawn for `gid` in Tiled, relative
+    /// to the tile's top-left corner, if it has any.
+    pub fn tile_shapes(&self, gid: u32) -> Option<&[TileShape]> {
+        self.tileset_for_gid(gid)
+            .and_then(|ts| ts.shapes.get(&(gid - ts.firstgid)))
+            .map(|s| &s[..])
+    }
+
+    /// Returns the collision shapes of every tile at `col`, `row`,
+    /// in world pixels. Tiles without shapes of their own are
+    /// full squares.
+    pub fn collision_shapes(&self, col: u32, row: u32) -> Vec<TileShape> {
+        let mut shapes = vec!();
+        let (x, y) = ((col * self.tile_width) as f64, (row * self.tile_height) as f64);
+        let cl = self.has_collision_layer();
+        for l in 0..self.layers.len() {
+            let gid = match self.tile_id(l, col, row) {
+                Some(gid) if self.is_solid_in(&self.layers[l], gid, cl) => gid,
+                _ => continue,
+            };
+            match self.tile_shapes(gid) {
+                Some(s) => shapes.extend(s.iter().map(|s| s.translate(x, y))),
+                None => shapes.push(TileShape::Rect {
+                    x: x,
+                    y: y,
+                    width: self.tile_width as f64,
+                    height: self.tile_height as f64,
+                }),
+            }
+        }
+        shapes
+    }
+
+    /// Index into a layer's `gids` of `col`, `row`.
+    fn tile_index(&self, col: u32, row: u32) -> usize {
+        (row * self.width + col) as usize
+    }
+
+    /// Add a layer named `name` on top of the existing ones,
+    /// filled from `data`, after adding `ts` to the map's tilesets.
+    pub fn insert_data_using_tilset(&mut self, name: &str, data: &[u32], ts: &Tileset) {
+        self.add_tileset(ts);
+        self.insert_data(name, data);
+    }
+
+    /// Add a layer named `name` on top of the existing ones, filled
+    /// from `data`. GIDs are resolved with the map's tilesets. Flip
+    /// flags aren't supported yet, so they're cleared.
+    pub fn insert_data(&mut self, name: &str, data: &[u32]) {
+        let len = (self.width * self.height) as usize;
+        let gids: Vec<u32> = data[..len].iter().map(|&gid| gid & !tiled::GID_FLAGS).collect();
+        for &gid in &gids {
+            self.add_animation(gid);
+        }
+
+        self.layers.push(TileLayer::new(name, gids));
+        self.invalidate_cache();
+        self.rebuild_collision();
+    }
+
+    /// Add the tiles of `layer` on top of the existing layers, keeping
+    /// its offset, parallax, opacity, visibility and tint. Layers
+    /// without tiles are ignored. A layer whose `collides` property is
+    /// false is only drawn. A layer named `collision`, or whose
+    /// `collision` property is true, is the map's collision layer.
+    pub fn insert_tiled_layer(&mut self, layer: &tiled::Layer) {
+        let n = self.layers.len();
+        match layer.kind {
+            tiled::LayerKind::Tiles(ref data) => self.insert_data(&layer.name, data),
+            tiled::LayerKind::Chunks(ref chunks) => self.insert_chunks(&layer.name, chunks),
+            _ => return,
+        }
+        if let Some(l) = self.layers.get_mut(n) {
+            l.offset_x = layer.offsetx as i64;
+            l.offset_y = layer.offsety as i64;
+            l.parallax_x = layer.parallaxx;
+            l.parallax_y = layer.parallaxy;
+            l.visible = layer.visible;
+            l.opacity = layer.opacity;
+            l.tint = layer.tintcolor;
+            l.collides = layer.properties.get_bool("collides").unwrap_or(true);
+            l.collision = layer.name == COLLISION_LAYER || layer.properties.get_bool("collision") == Some(true);
+            l.foreground = layer.properties.get_bool("foreground").unwrap_or(false);
+        }
+        if layer.properties.get_bool("collides") == Some(false) || self.has_collision_layer() {
+            self.rebuild_collision();
+        }
+    }
+
+    /// Add the tiles of every layer in `layers`, which should be
+    /// flattened, in order, so later layers are drawn on top.
+    pub fn insert_tiled_layers(&mut self, layers: &[tiled::Layer]) {
+        for l in layers {
+            self.insert_tiled_layer(l);
+        }
+    }
+
+    /// Index in `layers` of the layer named `name`.
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    /// Whether `gid` is a tile that can be collided with.
+    pub fn is_solid_gid(&self, gid: u32) -> bool {
+        match self.tileset_for_gid(gid) {
+            Some(ts) => ts.tile_for_id(gid).is_some() && !ts.passable.contains(&(gid - ts.firstgid)),
+            None => false,
+        }
+    }
+
+    /// Whether the map has a collision layer.
+    pub fn has_collision_layer(&self) -> bool {
+        self.layers.iter().any(|l| l.collision)
+    }
+
+    /// Whether `gid` in `layer` is solid. With a collision layer, any
+    /// of its tiles is, and no other layer's are.
+    fn is_solid_in(&self, layer: &TileLayer, gid: u32, collision_layer: bool) -> bool {
+        if collision_layer {
+            return layer.collision && gid != 0;
+        }
+        return layer.collides && self.is_solid_gid(gid);
+    }
+
+    /// Returns the map as a grid of cell flags for pathfinding. Cells
+    /// are solid as in `collision`, one-way where a tile's `one_way`
+    /// property is true, and hazards where a tile's `hazard` property
+    /// is true or a hazard object can reach.
+    pub fn collision_grid(&self) -> CollisionGrid {
+        let mut grid = CollisionGrid::new(self.width, self.height);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                if self.collision.is_solid(col, row) {
+                    grid.insert(col, row, collision::CELL_SOLID);
+                }
+                let i = self.tile_index(col, row);
+                for l in self.layers.iter() {
+                    if let Some(p) = self.tile_properties(l.gids[i]) {
+                        if p.get_bool("one_way") == Some(true) {
+                            grid.insert(col, row, collision::CELL_ONE_WAY);
+                        }
+                        if p.get_bool("hazard") == Some(true) {
+                            grid.insert(col, row, collision::CELL_HAZARD);
+                        }
+                        if p.get_bool("climbable") == Some(true) {
+                            grid.insert(col, row, collision::CELL_CLIMBABLE);
+                        }
+                    }
+                }
+            }
+        }
+        for h in self.hazards.iter() {
+            let reach = h.length + h.radius;
+            let area = physics::Rect::new((h.pivot.x - reach) as i32, (h.pivot.y - reach) as i32,
+                (2.0 * reach) as u32, (2.0 * reach) as u32);
+            if let Some((c0, r0, c1, r1)) = physics::tiles_in_rect(&self.collision, self.tile_width, self.tile_height, &area) {
+                for row in r0..r1 + 1 {
+                    for col in c0..c1 + 1 {
+                        grid.insert(col, row, collision::CELL_HAZARD);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// Whether `rect`, in world pixels, overlaps a tile in any
+    /// layer whose `climbable` property is true.
+    pub fn is_climbable(&self, rect: &physics::Rect) -> bool {
+        let (c0, r0, c1, r1) = match physics::tiles_in_rect(&self.collision, self.tile_width, self.tile_height, rect) {
+            Some(range) => range,
+            None => return false,
+        };
+        for row in r0..r1 + 1 {
+            for col in c0..c1 + 1 {
+                let i = self.tile_index(col, row);
+                let climbable = self.layers.iter().any(|l| {
+                    self.tile_properties(l.gids[i]).and_then(|p| p.get_bool("climbable")) == Some(true)
+                });
+                if climbable {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Whether any colliding layer has a solid tile at `col`, `row`.
+    fn has_solid_tile(&self, col: u32, row: u32) -> bool {
+        let (i, cl) = (self.tile_index(col, row), self.has_collision_layer());
+        self.layers.iter().any(|l| self.is_solid_in(l, l.gids[i], cl))
+    }
+
+    /// Recompute `collision` from every layer.
+    pub fn rebuild_collision(&mut self) {
+        let mut mask = CollisionMask::new(self.width, self.height);
+        for row in 0..self.height {
+            for col in 0..self.width {
+                mask.set(col, row, self.has_solid_tile(col, row));
+            }
+        }
+        self.collision = mask;
+    }
+
+    pub fn is_solid(&self, col: u32, row: u32) -> bool {
+        self.collision.is_solid(col, row)
+    }
+
+    /// Returns the inclusive range of tiles `rect` (in world pixels)
+    /// covers, clamped to the map, as first column, first row, last
+    /// column and last row. `None` if it's entirely outside the map.
+    pub fn tiles_in_rect(&self, rect: &physics::Rect) -> Option<(u32, u32, u32, u32)> {
+        physics::tiles_in_rect(&self.collision, self.tile_width, self.tile_height, rect)
+    }
+
+    /// Whether any tile `rect` (in world pixels) overlaps is solid.
+    pub fn rect_is_solid(&self, rect: &physics::Rect) -> bool {
+        physics::rect_is_solid(&self.collision, self.tile_width, self.tile_height, rect)
+    }
+
+    /// Returns the column and row of every solid tile
+    /// `rect` (in world pixels) overlaps.
+    pub fn solid_tiles_in_rect(&self, rect: &physics::Rect) -> Vec<(u32, u32)> {
+        physics::solid_tiles_in_rect(&self.collision, self.tile_width, self.tile_height, rect)
     }
 
-    pub fn insert_data_using_tilset(&mut self, data: &[u8], ts: &Tileset) {
-        for i in 0..self.height {
-            let _i = i as usize;
-            self.tiles.push(Vec::with_capacity(self.width as usize));
+    /// Returns the tile `col`, `row` refers to once wrapped along
+    /// the axes whose `bounds` wrap, or `None` if it's outside
+    /// the map.
+    pub fn wrap_tile(&self, col: i64, row: i64) -> Option<(u32, u32)> {
+        let (w, h) = (self.width as i64, self.height as i64);
+        let col = if self.bounds.x == Edge::Wrap { ((col % w) + w) % w } else { col };
+        let row = if self.bounds.y == Edge::Wrap { ((row % h) + h) % h } else { row };
+        if col < 0 || col >= w || row < 0 || row >= h {
+            return None;
+        }
+        return Some((col as u32, row as u32));
+    }
+
+    /// Like `is_solid`, but `col`, `row` wrap around the map.
+    pub fn is_solid_wrapped(&self, col: i64, row: i64) -> bool {
+        match self.wrap_tile(col, row) {
+            Some((c, r)) => self.is_solid(c, r),
+            None => false,
+        }
+    }
+
+    /// Like `collision_shapes`, but `col`, `row` wrap around the map.
+    /// The shapes are placed at `col`, `row` rather than at the tile
+    /// they wrap to.
+    pub fn collision_shapes_wrapped(&self, col: i64, row: i64) -> Vec<TileShape> {
+        let (c, r) = match self.wrap_tile(col, row) {
+            Some(t) => t,
+            None => return vec!(),
+        };
+        let dx = (col - c as i64) * self.tile_width as i64;
+        let dy = (row - r as i64) * self.tile_height as i64;
+        self.collision_shapes(c, r).iter().map(|s| s.translate(dx as f64, dy as f64)).collect()
+    }
 
-            for j in 0..self.width {
-                let _j = j as usize;
-                self.tiles[_i].push(Tile::new(ts.texture.clone(),
-                    ts.tile_for_id(data[_i*self.width as usize + _j] as u32)));
+    /// Offsets, in world pixels, at which the map has to be drawn
+    /// so that the copies across any wrapping edge show at the seam.
+    fn wrap_offsets(&self) -> Vec<(i32, i32)> {
+        let (w, h) = (self.pixel_width() as i32, self.pixel_height() as i32);
+        let xs = if self.bounds.x == Edge::Wrap { vec!(-w, 0, w) } else { vec!(0) };
+        let ys = if self.bounds.y == Edge::Wrap { vec!(-h, 0, h) } else { vec!(0) };
+        let mut offsets = vec!();
+        for &y in &ys {
+            for &x in &xs {
+                offsets.push((x, y));
             }
         }
+        offsets
+    }
+
+    /// Throw away every cached chunk, so it's redrawn from the
+    /// layers. Needed after changing `layers` directly rather
+    /// than through `set_tile`.
+    pub fn invalidate_cache(&mut self) {
+        for l in self.layers.iter_mut() {
+            l.cache.clear();
+        }
+    }
+
+    /// Number of cache chunks across and down the map.
+    fn cache_chunks(&self) -> (u32, u32) {
+        ((self.width + CACHE_CHUNK_SIZE - 1) / CACHE_CHUNK_SIZE,
+         (self.height + CACHE_CHUNK_SIZE - 1) / CACHE_CHUNK_SIZE)
+    }
+
+    fn cache_chunk_index(&self, cx: u32, cy: u32) -> usize {
+        (cy * self.cache_chunks().0 + cx) as usize
+    }
+
+    /// How far, in pixels, tiles bigger than the map's cells stick
+    /// out to the right and upwards.
+    fn tile_overhang(&self) -> (u32, u32) {
+        let w = self.tilesets.iter().map(|ts| ts.tile_width).max().unwrap_or(0);
+        let h = self.tilesets.iter().map(|ts| ts.tile_height).max().unwrap_or(0);
+        (w.saturating_sub(self.tile_width), h.saturating_sub(self.tile_height))
+    }
+
+    /// Returns the area, in world pixels, covered by cache chunk `cx`,
+    /// `cy`, including room for tiles that stick out of it.
+    fn cache_chunk_rect(&self, cx: u32, cy: u32) -> Rect {
+        let (ox, oy) = self.tile_overhang();
+        let cols = ::std::cmp::min(CACHE_CHUNK_SIZE, self.width - cx*CACHE_CHUNK_SIZE);
+        let rows = ::std::cmp::min(CACHE_CHUNK_SIZE, self.height - cy*CACHE_CHUNK_SIZE);
+        Rect::new_unwrap((cx*CACHE_CHUNK_SIZE*self.tile_width) as i32,
+            (cy*CACHE_CHUNK_SIZE*self.tile_height) as i32 - oy as i32,
+            cols*self.tile_width + ox, rows*self.tile_height + oy)
+    }
+
+    /// Draw the static tiles of chunk `cx`, `cy` of `layer` into
+    /// a new texture. `None` if the renderer can't do that.
+    fn render_cache_chunk(&self, r: &mut Renderer, layer: usize, cx: u32, cy: u32) -> Option<Texture> {
+        let area = self.cache_chunk_rect(cx, cy);
+        let mut tx = match r.create_texture_target(PixelFormatEnum::RGBA8888, (area.width(), area.height())) {
+            Ok(tx) => tx,
+            Err(_) => return None,
+        };
+        tx.set_blend_mode(BlendMode::Blend);
+        // drawing may already be redirected, e.g. by an EffectChain
+        let prev = match r.render_target().map(|mut rt| rt.set(tx)) {
+            Some(Ok(prev)) => prev,
+            _ => return None,
+        };
+
+        let draw_color = r.draw_color();
+        r.set_draw_color(Color::RGBA(0, 0, 0, 0));
+        r.clear();
+        let l = &self.layers[layer];
+        let (first_col, first_row) = (cx*CACHE_CHUNK_SIZE, cy*CACHE_CHUNK_SIZE);
+        for row in first_row..::std::cmp::min(first_row + CACHE_CHUNK_SIZE, self.height) {
+            for col in first_col..::std::cmp::min(first_col + CACHE_CHUNK_SIZE, self.width) {
+                let gid = l.gids[self.tile_index(col, row)];
+                if self.tile_animations.contains_key(&gid) {
+                    continue;
+                }
+                let ts = match self.tileset_for_gid(gid) {
+                    Some(ts) => ts,
+                    None => continue,
+                };
+                if let Some(clip_rect) = ts.tile_for_id(gid) {
+                    let (x, y) = self.tile_to_pixel(col as i32, row as i32);
+                    let y = y + self.tile_height as i32 - ts.tile_height as i32;
+                    r.copy(&ts.texture.borrow(), Some(clip_rect),
+                        Some(Rect::new_unwrap(x - area.x(), y - area.y(), ts.tile_width, ts.tile_height)));
+                }
+            }
+        }
+        r.set_draw_color(draw_color);
+
+        let chunk = match r.render_target() {
+            Some(mut rt) => match prev {
+                Some(prev) => rt.set(prev),
+                None => rt.reset(),
+            },
+            None => return None,
+        };
+        match chunk {
+            Ok(Some(chunk)) => Some(chunk),
+            _ => None,
+        }
+    }
+
+    /// Draw the static tiles of `layer` from its cache, rendering any
+    /// visible chunks that aren't cached yet. Returns false, and stops
+    /// caching, if the renderer can't render to textures.
+    fn draw_cached(&mut self, r: &mut Renderer, c: &Camera, layer: usize, view: (i32, i32), offsets: &[(i32, i32)]) -> bool {
+        let (chunks_x, chunks_y) = self.cache_chunks();
+        let n = (chunks_x * chunks_y) as usize;
+        if self.layers[layer].cache.len() != n {
+            self.layers[layer].cache = vec![None; n];
+        }
+
+        let (vx, vy) = view;
+        for &(ox, oy) in offsets {
+            let (first_col, first_row, last_col, last_row) = match self.visible_tiles(c, view, (ox, oy)) {
+                Some(range) => range,
+                None => continue,
+            };
+            for cy in first_row/CACHE_CHUNK_SIZE..last_row/CACHE_CHUNK_SIZE + 1 {
+                for cx in first_col/CACHE_CHUNK_SIZE..last_col/CACHE_CHUNK_SIZE + 1 {
+                    let area = self.cache_chunk_rect(cx, cy);
+                    let k = self.cache_chunk_index(cx, cy);
+                    if self.layers[layer].cache[k].is_none() {
+                        match self.render_cache_chunk(r, layer, cx, cy) {
+                            Some(tx) => self.layers[layer].cache[k] = Some(Rc::new(RefCell::new(tx))),
+                            None => {
+                                self.cache_tiles = false;
+                                return false;
+                            },
+                        }
+                    }
+
+                    let l = &self.layers[layer];
+                    let mut tx = l.cache[k].as_ref().unwrap().borrow_mut();
+                    set_layer_mods(&mut tx, l.opacity, l.tint);
+                    r.copy(&tx, None, Some(Rect::new_unwrap(area.x() + ox - vx, area.y() + oy - vy,
+                        area.width(), area.height())));
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the inclusive range of tiles that can be seen through a
+    /// `c`-sized view at `view`, in the copy of the map drawn at `offset`,
+    /// as first column, first row, last column and last row. `None` if
+    /// none can.
+    fn visible_tiles(&self, c: &Camera, view: (i32, i32), offset: (i32, i32)) -> Option<(u32, u32, u32, u32)> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let (x0, y0) = (view.0 - offset.0, view.1 - offset.1);
+        let (x1, y1) = (x0 + c.width as i32, y0 + c.height as i32);
+        let corners = [self.pixel_to_tile(x0, y0), self.pixel_to_tile(x1, y0),
+                       self.pixel_to_tile(x0, y1), self.pixel_to_tile(x1, y1)];
+        // tiles bigger than their cells can be seen from outside
+        // the view; other layouts get a tile of slack all round
+        let (ox, oy) = self.tile_overhang();
+        let pad_x = ((ox + self.tile_width - 1) / self.tile_width) as i32;
+        let pad_y = ((oy + self.tile_height - 1) / self.tile_height) as i32;
+        let (pad_x, pad_y, slack) = match self.orientation {
+            tiled::Orientation::Orthogonal => (pad_x, pad_y, 0),
+            _ => (0, 0, 1 + pad_x + pad_y),
+        };
+        let first_col = corners.iter().map(|t| t.0).min().unwrap() - pad_x - slack;
+        let first_row = corners.iter().map(|t| t.1).min().unwrap() - slack;
+        let last_col = corners.iter().map(|t| t.0).max().unwrap() + slack;
+        let last_row = corners.iter().map(|t| t.1).max().unwrap() + pad_y + slack;
+        if last_col < 0 || last_row < 0 || first_col >= self.width as i32 || first_row >= self.height as i32 {
+            return None;
+        }
+        let clamp = |v: i32, max: u32| if v < 0 { 0 } else if v >= max as i32 { max - 1 } else { v as u32 };
+        return Some((clamp(first_col, self.width), clamp(first_row, self.height),
+                     clamp(last_col, self.width), clamp(last_row, self.height)));
+    }
+
+    /// Draw the visible tiles of `layer`, or only its animated
+    /// ones if the rest were drawn from the cache.
+    fn draw_tiles(&self, r: &mut Renderer, c: &Camera, layer: usize, view: (i32, i32), offsets: &[(i32, i32)], animated_only: bool) {
+        let (vx, vy) = view;
+        let gids = &self.layers[layer].gids;
+        for &(ox, oy) in offsets {
+            let (first_col, first_row, last_col, last_row) = match self.visible_tiles(c, view, (ox, oy)) {
+                Some(range) => range,
+                None => continue,
+            };
+            for row in first_row..last_row + 1 {
+                for col in first_col..last_col + 1 {
+                    let gid = gids[self.tile_index(col, row)];
+                    let ts = match self.tileset_for_gid(gid) {
+                        Some(ts) => ts,
+                        None => continue,
+                    };
+                    let clip_rect = match self.tile_animations.get(&gid) {
+                        Some(anim) => anim.clip_rect(),
+                        None if animated_only => continue,
+                        None => ts.tile_for_id(gid),
+                    };
+                    if clip_rect == None {
+                        continue;
+                    }
+
+                    let (x, y) = self.tile_to_pixel(col as i32, row as i32);
+                    // tiles taller than the map's cells stick out upwards
+                    let (x, y) = (x + ox, y + oy + self.tile_height as i32 - ts.tile_height as i32);
+                    r.copy(&ts.texture.borrow(), clip_rect,
+                        Some(Rect::new_unwrap(x - vx, y - vy,
+                            ts.tile_width, ts.tile_height)));
+                }
+            }
+        }
+    }
+
+    /// Add `il` on top of the existing tile layers.
+    pub fn add_image_layer(&mut self, mut il: ImageLayer) {
+        il.below = self.layers.len();
+        self.image_layers.push(il);
+    }
+
+    fn add_animation(&mut self, gid: u32) {
+        if !self.tile_animations.contains_key(&gid) {
+            if let Some(anim) = self.tileset_for_gid(gid).and_then(|ts| ts.animation_for_id(gid)) {
+                self.tile_animations.insert(gid, anim);
+            }
+        }
+    }
+
+    /// Advance tile animations, hazards and decals by `dt` milliseconds.
+    pub fn update(&mut self, dt: u32) {
+        for anim in self.tile_animations.values_mut() {
+            anim.update(dt);
+        }
+        self.elapsed = self.elapsed.wrapping_add(dt);
+        for h in self.hazards.iter_mut() {
+            h.update(self.elapsed);
+        }
+        self.decals.update(dt);
+    }
+
+    /// Grow the map to cover columns `bounds.0` to `bounds.2` and
+    /// rows `bounds.1` to `bounds.3`, in Tiled's coordinates, moving
+    /// the existing layers' tiles to match. `bounds` must contain
+    /// the current map.
+    fn resize_tiles(&mut self, bounds: (i32, i32, i32, i32)) {
+        let (c0, r0, c1, r1) = bounds;
+        let (width, height) = ((c1 - c0) as u32, (r1 - r0) as u32);
+        if (c0, r0, width, height) == (self.origin_col, self.origin_row, self.width, self.height) {
+            return;
+        }
+        let (dc, dr) = ((self.origin_col - c0) as u32, (self.origin_row - r0) as u32);
+        for l in self.layers.iter_mut() {
+            let mut gids = vec![0; (width * height) as usize];
+            for row in 0..self.height {
+                for col in 0..self.width {
+                    gids[((row + dr) * width + col + dc) as usize] = l.gids[(row * self.width + col) as usize];
+                }
+            }
+            l.gids = gids;
+        }
+        self.origin_col = c0;
+        self.origin_row = r0;
+        self.width = width;
+        self.height = height;
+        self.invalidate_cache();
+        self.rebuild_collision();
+    }
+
+    /// Add a layer named `name` filled from the chunks of an infinite
+    /// map, after adding `ts` to the map's tilesets.
+    pub fn insert_chunks_using_tileset(&mut self, name: &str, chunks: &[tiled::Chunk], ts: &Tileset) {
+        self.add_tileset(ts);
+        self.insert_chunks(name, chunks);
+    }
+
+    /// Add a layer named `name` filled from the chunks of an infinite
+    /// map. The map grows to cover `chunks` as well as the existing
+    /// layers; gaps between chunks are left empty. The map's top-left
+    /// tile is drawn at (0, 0) in world space, whatever `origin_col`
+    /// and `origin_row` are.
+    pub fn insert_chunks(&mut self, name: &str, chunks: &[tiled::Chunk]) {
+        let bounds = match chunk_bounds(chunks) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let current = (self.origin_col, self.origin_row,
+                       self.origin_col + self.width as i32, self.origin_row + self.height as i32);
+        self.resize_tiles(union_bounds(current, bounds));
+        let (min_col, min_row) = (self.origin_col, self.origin_row);
+
+        let mut gids = vec![0; (self.width * self.height) as usize];
+        for c in chunks {
+            for (k, &gid) in c.data.iter().enumerate() {
+                let col = (c.x - min_col) as u32 + k as u32 % c.width;
+                let row = (c.y - min_row) as u32 + k as u32 / c.width;
+                let gid = gid & !tiled::GID_FLAGS;
+                gids[self.tile_index(col, row)] = gid;
+                self.add_animation(gid);
+            }
+        }
+
+        self.layers.push(TileLayer::new(name, gids));
+        self.invalidate_cache();
+        self.rebuild_collision();
+    }
+
+    /// Change the tile at `col`, `row` of `layer` to `gid` (0 clears
+    /// it), updating the collision mask. Returns the previous GID, or
+    /// `None` if the position is outside the map.
+    pub fn set_tile(&mut self, layer: usize, col: u32, row: u32, gid: u32) -> Option<u32> {
+        if col >= self.width || row >= self.height || layer >= self.layers.len() {
+            return None;
+        }
+
+        let gid = gid & !tiled::GID_FLAGS;
+        self.add_animation(gid);
+        let i = self.tile_index(col, row);
+        let old = self.layers[layer].gids[i];
+        self.layers[layer].gids[i] = gid;
+        let chunk = self.cache_chunk_index(col / CACHE_CHUNK_SIZE, row / CACHE_CHUNK_SIZE);
+        if let Some(c) = self.layers[layer].cache.get_mut(chunk) {
+            *c = None;
+        }
+
+        let solid = self.has_solid_tile(col, row);
+        self.collision.set(col, row, solid);
+        Some(old)
+    }
+
+    /// Remove the tile at `col`, `row` of `layer`, e.g. when a brick
+    /// is broken. Returns the GID that was there, as `set_tile` does.
+    pub fn clear_tile(&mut self, layer: usize, col: u32, row: u32) -> Option<u32> {
+        self.set_tile(layer, col, row, 0)
+    }
+
+    /// Returns the GID at `col`, `row` of `layer`, if it's in the map.
+    pub fn tile_id(&self, layer: usize, col: u32, row: u32) -> Option<u32> {
+        if col >= self.width || row >= self.height {
+            return None;
+        }
+        self.layers.get(layer).map(|l| l.gids[self.tile_index(col, row)])
+    }
+
+    /// Returns the column and row of the tile at world pixel `x`, `y`,
+    /// wrapped if the map wraps, or `None` if it's outside the map.
+    pub fn tile_at(&self, x: f64, y: f64) -> Option<(u32, u32)> {
+        let (col, row) = self.pixel_to_tile(x.floor() as i32, y.floor() as i32);
+        self.wrap_tile(col as i64, row as i64)
+    }
+
+    /// Returns the column and row of the tile at world position `p`,
+    /// wrapped if the map wraps, or `None` if it's outside the map.
+    pub fn world_to_tile(&self, p: Vec2) -> Option<(u32, u32)> {
+        self.tile_at(p.x, p.y)
+    }
+
+    /// Returns the cell the tile at `col`, `row` is drawn in, in
+    /// world pixels. Tiles taller than the map's cells stick out
+    /// above it.
+    pub fn tile_to_world(&self, col: u32, row: u32) -> physics::Rect {
+        let (x, y) = self.tile_to_pixel(col as i32, row as i32);
+        physics::Rect::new(x, y, self.tile_width, self.tile_height)
+    }
+
+    /// Returns the GID of `layer` at world pixel `x`, `y`.
+    pub fn tile_id_at(&self, layer: usize, x: f64, y: f64) -> Option<u32> {
+        self.tile_at(x, y).and_then(|(col, row)| self.tile_id(layer, col, row))
+    }
+
+    /// Returns the custom properties of `gid`, if it has any.
+    pub fn tile_properties(&self, gid: u32) -> Option<&tiled::Properties> {
+        self.tileset_for_gid(gid).and_then(|ts| ts.properties.get(&(gid - ts.firstgid)))
+    }
+
+    /// Returns the GID of `layer` at world pixel `x`, `y` and its
+    /// custom properties, if the tile isn't empty and has any.
+    pub fn properties_at(&self, layer: usize, x: f64, y: f64) -> Option<(u32, &tiled::Properties)> {
+        self.tile_id_at(layer, x, y).and_then(|gid| self.tile_properties(gid).map(|p| (gid, p)))
     }
 }
 
-impl CameraDrawable for Map {
+/// A single image drawn between a map's tile layers, optionally
+/// repeated to fill the view horizontally and/or vertically.
+#[derive(Clone)]
+pub struct ImageLayer {
+    pub name: String,
+    pub texture: Rc<RefCell<Texture>>,
+    pub offset_x: i64,
+    pub offset_y: i64,
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+    pub parallax_x: f64,
+    pub parallax_y: f64,
+    pub visible: bool,
+    pub opacity: f64,
+    pub tint: Option<tiled::Color>,
+    /// Number of tile layers drawn under the image.
+    pub below: usize,
+    /// Whether the image is drawn with the foreground layers.
+    pub foreground: bool,
+}
+
+impl ImageLayer {
+    pub fn new_from_tiled_layer(img_path: &Path, layer: &tiled::Layer, il: &tiled::ImageLayer, r: &Renderer) -> Result<Self, tiled::ReadError> {
+        Ok(ImageLayer {
+            name: layer.name.clone(),
+            texture: Rc::new(RefCell::new(try!(load_image(img_path, r)))),
+            offset_x: layer.offsetx as i64,
+            offset_y: layer.offsety as i64,
+            repeat_x: il.repeatx,
+            repeat_y: il.repeaty,
+            parallax_x: layer.parallaxx,
+            parallax_y: layer.parallaxy,
+            visible: layer.visible,
+            opacity: layer.opacity,
+            tint: layer.tintcolor,
+            below: 0,
+            foreground: layer.properties.get_bool("foreground").unwrap_or(false),
+        })
+    }
+
+    /// Returns the positions along one axis at which to draw an image
+    /// of length `len` so that it covers `view..view+view_len`.
+    fn positions(offset: i64, len: i64, repeat: bool, view: i64, view_len: i64) -> Vec<i64> {
+        if !repeat {
+            return vec!(offset);
+        }
+        let first = offset + ((view - offset) as f64 / len as f64).floor() as i64 * len;
+        (0..).map(|k| first + k*len).take_while(|&p| p < view + view_len).collect()
+    }
+}
+
+impl CameraDrawable for ImageLayer {
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        if !self.visible {
+            return;
+        }
+        let mut tx = self.texture.borrow_mut();
+        let q = tx.query();
+        let (w, h) = (q.width as i64, q.height as i64);
+        set_layer_mods(&mut tx, self.opacity, self.tint);
+        // offsets are applied by `positions`
+        let (vx, vy) = layer_view(c, (0, 0), (self.parallax_x, self.parallax_y));
+        let (cw, ch) = (c.width as i64, c.height as i64);
+        for y in ImageLayer::positions(self.offset_y, h, self.repeat_y, vy, ch) {
+            for x in ImageLayer::positions(self.offset_x, w, self.repeat_x, vx, cw) {
+                if x + w < vx || x > vx + cw { continue }
+                if y + h < vy || y > vy + ch { continue }
+                r.copy(&tx, None,
+                    Some(Rect::new_unwrap((x - vx) as i32, (y - vy) as i32, w as u32, h as u32)));
+            }
+        }
+        set_layer_mods(&mut tx, 1.0, None);
+    }
+}
+
+/// How a `Sky`'s image fills the view.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SkyFill {
+    /// Scaled to the size of the view.
+    Stretch,
+    /// Repeated from the view's top-left corner.
+    Tile,
+}
+
+impl SkyFill {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "stretch" => Some(SkyFill::Stretch),
+            "tile" => Some(SkyFill::Tile),
+            _ => None,
+        }
+    }
+}
+
+/// What's drawn behind a map's `Background`, fixed to the view:
+/// an optional vertical gradient, then an optional image.
+///
+/// Without either, the screen's clear color (the map's background
+/// color) shows through.
+#[derive(Clone)]
+pub struct Sky {
+    /// Color at the top of the gradient. Black if `None`.
+    pub top: Option<tiled::Color>,
+    /// Color at the bottom of the gradient. No gradient is
+    /// drawn if `None`.
+    pub bottom: Option<tiled::Color>,
+    pub texture: Option<Rc<RefCell<Texture>>>,
+    pub fill: SkyFill,
+}
+
+impl Sky {
+    /// Read the sky from the map properties `sky_top` (defaulting
+    /// to `background`, the map's background color), `sky_bottom`
+    /// and `sky_fill` (`stretch` or `tile`, defaulting to
+    /// `stretch`). The image, from `sky_image`, is loaded by
+    /// `Map::load`.
+    pub fn from_properties(background: Option<tiled::Color>, p: &tiled::Properties) -> Self {
+        Sky {
+            top: p.get_color("sky_top").or(background),
+            bottom: p.get_color("sky_bottom"),
+            texture: None,
+            fill: p.get_str("sky_fill").and_then(SkyFill::parse).unwrap_or(SkyFill::Stretch),
+        }
+    }
+
+    fn draw_gradient(&self, r: &mut Renderer, c: &Camera) {
+        let bottom = match self.bottom {
+            Some(bottom) => bottom,
+            None => return,
+        };
+        let top = self.top.unwrap_or(tiled::Color { r: 0, g: 0, b: 0, a: 255 });
+        let blend = |a: u8, b: u8, t: f64| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        let (w, h) = (c.width as i32, c.height as i32);
+        let draw_col = r.draw_color();
+        for y in 0..h {
+            let t = if h > 1 { y as f64 / (h - 1) as f64 } else { 0.0 };
+            r.set_draw_color(Color::RGBA(blend(top.r, bottom.r, t), blend(top.g, bottom.g, t),
+                blend(top.b, bottom.b, t), blend(top.a, bottom.a, t)));
+            r.draw_line(Point::new(0, y), Point::new(w, y));
+        }
+        r.set_draw_color(draw_col);
+    }
+}
+
+impl CameraDrawable for Sky {
     fn draw(&mut self, r: &mut Renderer, c: &Camera) {
-        for (i, row) in self.tiles.iter().enumerate() {
-            let i = i as i32;
-            for (j, tile) in row.iter().enumerate() {
-                if tile.clip_rect == None {
+        self.draw_gradient(r, c);
+        let tx = match self.texture {
+            Some(ref tx) => tx.borrow(),
+            None => return,
+        };
+        let (cw, ch) = (c.width as u32, c.height as u32);
+        match self.fill {
+            SkyFill::Stretch => r.copy(&tx, None, Some(Rect::new_unwrap(0, 0, cw, ch))),
+            SkyFill::Tile => {
+                let q = tx.query();
+                let (w, h) = (q.width as i64, q.height as i64);
+                for y in ImageLayer::positions(0, h, true, 0, ch as i64) {
+                    for x in ImageLayer::positions(0, w, true, 0, cw as i64) {
+                        r.copy(&tx, None, Some(Rect::new_unwrap(x as i32, y as i32, w as u32, h as u32)));
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Where a background layer sits vertically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    /// The image's top is `offset_y` pixels below the top of the view.
+    Top,
+    /// The image's bottom is `offset_y` pixels below the bottom
+    /// of the view, so a negative offset raises it.
+    Bottom,
+    /// The image's top is at world y `offset_y`, scrolling
+    /// vertically at the layer's `scroll_y`.
+    World,
+}
+
+/// One image of a `Background`, scrolling at a fraction of the
+/// camera's speed: 0 stays put, 1 moves with the map.
+#[derive(Clone)]
+pub struct BackgroundLayer {
+    pub texture: Rc<RefCell<Texture>>,
+    pub scroll_x: f64,
+    pub scroll_y: f64,
+    pub offset_x: i64,
+    pub offset_y: i64,
+    /// Whether the image is repeated to fill the view horizontally.
+    pub repeat_x: bool,
+    pub anchor: Anchor,
+}
+
+impl BackgroundLayer {
+    /// A layer that scrolls at `scroll_x`, `scroll_y`, repeated
+    /// horizontally and with its top at the top of the world.
+    pub fn new(texture: Rc<RefCell<Texture>>, scroll_x: f64, scroll_y: f64) -> Self {
+        BackgroundLayer {
+            texture: texture,
+            scroll_x: scroll_x,
+            scroll_y: scroll_y,
+            offset_x: 0,
+            offset_y: 0,
+            repeat_x: true,
+            anchor: Anchor::World,
+        }
+    }
+}
+
+impl CameraDrawable for BackgroundLayer {
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        let tx = self.texture.borrow();
+        let q = tx.query();
+        let (w, h) = (q.width as i64, q.height as i64);
+        let (cw, ch) = (c.width as i64, c.height as i64);
+        let vx = (c.pos.x * self.scroll_x) as i64;
+        let y = match self.anchor {
+            Anchor::Top => self.offset_y,
+            Anchor::Bottom => ch - h + self.offset_y,
+            Anchor::World => self.offset_y - (c.pos.y * self.scroll_y) as i64,
+        };
+        if y + h < 0 || y > ch {
+            return;
+        }
+        for x in ImageLayer::positions(self.offset_x, w, self.repeat_x, vx, cw) {
+            if x + w < vx || x > vx + cw { continue }
+            r.copy(&tx, None,
+                Some(Rect::new_unwrap((x - vx) as i32, y as i32, w as u32, h as u32)));
+        }
+    }
+}
+
+/// Parallax images drawn behind everything else in a map,
+/// farthest first.
+#[derive(Clone)]
+pub struct Background {
+    pub layers: Vec<BackgroundLayer>,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Background {
+            layers: Vec::new(),
+        }
+    }
+
+    /// Add `layer` in front of the existing layers.
+    pub fn push(&mut self, layer: BackgroundLayer) {
+        self.layers.push(layer);
+    }
+}
+
+impl CameraDrawable for Background {
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        for l in self.layers.iter_mut() {
+            l.draw(r, c);
+        }
+    }
+}
+
+/// Neighbour bits of an auto-tiling mask.
+pub const NEIGHBOUR_NW: u8 = 1 << 0;
+pub const NEIGHBOUR_N: u8 = 1 << 1;
+pub const NEIGHBOUR_NE: u8 = 1 << 2;
+pub const NEIGHBOUR_W: u8 = 1 << 3;
+pub const NEIGHBOUR_E: u8 = 1 << 4;
+pub const NEIGHBOUR_SW: u8 = 1 << 5;
+pub const NEIGHBOUR_S: u8 = 1 << 6;
+pub const NEIGHBOUR_SE: u8 = 1 << 7;
+
+/// Offsets of each neighbour, in the same order as the mask bits.
+const NEIGHBOUR_OFFSETS: [(i32, i32); 8] = [
+    (-1, -1), (0, -1), (1, -1),
+    (-1, 0), (1, 0),
+    (-1, 1), (0, 1), (1, 1),
+];
+
+/// Returns the mask of the neighbours of `col`, `row` in a `width` x
+/// `height` grid that are terrain, going by `filled`. Neighbours
+/// outside the grid count as terrain, so edges don't show at its
+/// borders.
+fn terrain_mask<F: Fn(u32, u32) -> bool>(col: u32, row: u32, width: u32, height: u32, filled: F) -> u8 {
+    let mut mask = 0;
+    for (bit, &(dx, dy)) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+        let (x, y) = (col as i32 + dx, row as i32 + dy);
+        if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 || filled(x as u32, y as u32) {
+            mask |= 1 << bit;
+        }
+    }
+    mask
+}
+
+/// A single auto-tiling rule: a tile whose neighbours, restricted
+/// to the bits in `mask`, equal `bits` is drawn as `gid`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AutoTileRule {
+    pub mask: u8,
+    pub bits: u8,
+    pub gid: u32,
+}
+
+/// A set of auto-tiling rules for one terrain, resolving each
+/// terrain tile to an edge or corner variant depending on which
+/// of its 8 neighbours are also terrain.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AutoTileRules {
+    /// Checked in order; the first matching rule wins.
+    pub rules: Vec<AutoTileRule>,
+    /// The GID used when no rule matches.
+    pub default: u32,
+}
+
+/// Clears the corner bits of `mask` whose two neighbouring edges
+/// aren't both set, leaving one of the 47 masks a "blob" tileset
+/// has a tile for. A corner only changes how a tile looks when
+/// both edges next to it are terrain too.
+pub fn blob_mask(mask: u8) -> u8 {
+    let corners = [
+        (NEIGHBOUR_NW, NEIGHBOUR_N | NEIGHBOUR_W),
+        (NEIGHBOUR_NE, NEIGHBOUR_N | NEIGHBOUR_E),
+        (NEIGHBOUR_SW, NEIGHBOUR_S | NEIGHBOUR_W),
+        (NEIGHBOUR_SE, NEIGHBOUR_S | NEIGHBOUR_E),
+    ];
+    let mut blob = mask & (NEIGHBOUR_N | NEIGHBOUR_W | NEIGHBOUR_E | NEIGHBOUR_S);
+    for &(corner, edges) in corners.iter() {
+        if mask & corner != 0 && mask & edges == edges {
+            blob |= corner;
+        }
+    }
+    blob
+}
+
+/// The 47 distinct blob masks, in increasing order.
+pub fn blob_masks() -> Vec<u8> {
+    let mut masks: Vec<u8> = (0..256u32).map(|m| blob_mask(m as u8)).collect();
+    masks.sort();
+    masks.dedup();
+    masks
+}
+
+impl AutoTileRule {
+    /// The rule matching exactly the neighbour masks that reduce
+    /// to blob mask `blob`: its edges must match, and so must the
+    /// corners between two terrain edges.
+    pub fn for_blob(blob: u8, gid: u32) -> Self {
+        let mut mask = NEIGHBOUR_N | NEIGHBOUR_W | NEIGHBOUR_E | NEIGHBOUR_S;
+        for &corner in [NEIGHBOUR_NW, NEIGHBOUR_NE, NEIGHBOUR_SW, NEIGHBOUR_SE].iter() {
+            if blob_mask(blob | corner) & corner != 0 {
+                mask |= corner;
+            }
+        }
+        AutoTileRule {
+            mask: mask,
+            bits: blob,
+            gid: gid,
+        }
+    }
+}
+
+/// Positions in a Wang ID, which goes clockwise from the top
+/// edge, of the neighbour bits they depend on.
+const WANG_NEIGHBOURS: [u8; 8] = [
+    NEIGHBOUR_N, NEIGHBOUR_NE, NEIGHBOUR_E, NEIGHBOUR_SE,
+    NEIGHBOUR_S, NEIGHBOUR_SW, NEIGHBOUR_W, NEIGHBOUR_NW,
+];
+
+impl AutoTileRules {
+    /// Rules for a 47-tile blob tileset whose tiles, from `first_gid`
+    /// on, are in the order of `blob_masks`. The default is the tile
+    /// surrounded on all sides.
+    pub fn blob(first_gid: u32) -> Self {
+        let masks = blob_masks();
+        AutoTileRules {
+            rules: masks.iter().enumerate()
+                .map(|(i, &m)| AutoTileRule::for_blob(m, first_gid + i as u32))
+                .collect(),
+            default: first_gid + masks.len() as u32 - 1,
+        }
+    }
+
+    /// Rules drawing terrain `color` (an index into the Wang set's
+    /// `colors` plus one) with the tiles of `ws`, in tileset `ts`.
+    /// A corner of a cell is terrain when the three cells around it
+    /// are, and an edge when the cell beside it is. Neighbour masks
+    /// without a matching tile use the default, the tile that's
+    /// terrain all round. `None` if `ws` doesn't have that tile.
+    pub fn from_wangset(ts: &Tileset, ws: &tiled::WangSet, color: u8) -> Option<Self> {
+        let positions: Vec<usize> = match &ws.wang_type[..] {
+            "edge" => vec!(0, 2, 4, 6),
+            "mixed" => (0..8).collect(),
+            _ => vec!(1, 3, 5, 7),
+        };
+        let find = |blob: u8| ws.wangtiles.iter().find(|t| positions.iter().all(|&i| {
+            let filled = t.wangid.get(i).map_or(false, |&c| c == color);
+            filled == (blob & WANG_NEIGHBOURS[i] != 0)
+        })).map(|t| ts.firstgid + t.tileid);
+
+        let default = match find(0xFF) {
+            Some(gid) => gid,
+            None => return None,
+        };
+        Some(AutoTileRules {
+            rules: blob_masks().into_iter()
+                .filter_map(|m| find(m).map(|gid| AutoTileRule::for_blob(m, gid)))
+                .collect(),
+            default: default,
+        })
+    }
+
+    pub fn read_json<P: AsRef<Path>>(path: P) -> Result<Self, tiled::ReadError> {
+        let path = path.as_ref();
+        let f = try!(File::open(path).map_err(|e| tiled::ReadError::from(e).in_file(path)));
+        serde_json::from_reader(BufReader::new(f)).map_err(|e| tiled::ReadError::from(e).in_file(path))
+    }
+
+    /// Whether `gid` belongs to this terrain.
+    pub fn contains(&self, gid: u32) -> bool {
+        gid != 0 && (gid == self.default || self.rules.iter().any(|r| r.gid == gid))
+    }
+
+    /// Returns the GID to use for a terrain tile with neighbours `mask`.
+    pub fn gid_for(&self, mask: u8) -> u32 {
+        match self.rules.iter().find(|r| mask & r.mask == r.bits) {
+            Some(r) => r.gid,
+            None => self.default,
+        }
+    }
+}
+
+impl Map {
+    /// Returns the mask of the neighbours of `col`, `row` in `layer`
+    /// that belong to the terrain in `rules`. Neighbours outside
+    /// the map count as terrain, so edges don't show at map borders.
+    pub fn neighbour_mask(&self, layer: usize, col: u32, row: u32, rules: &AutoTileRules) -> u8 {
+        terrain_mask(col, row, self.width, self.height,
+            |x, y| self.tile_id(layer, x, y).map_or(false, |gid| rules.contains(gid)))
+    }
+
+    /// Fill `layer` with terrain wherever `terrain` is solid, choosing
+    /// each tile's variant from its neighbours in `terrain`, and clear
+    /// it everywhere else. For generated levels, whose terrain is
+    /// decided before any tiles are.
+    pub fn autotile_from_mask(&mut self, layer: usize, terrain: &CollisionMask, rules: &AutoTileRules) {
+        let (w, h) = (self.width.min(terrain.width), self.height.min(terrain.height));
+        for row in 0..h {
+            for col in 0..w {
+                let gid = if terrain.is_solid(col, row) {
+                    rules.gid_for(terrain_mask(col, row, terrain.width, terrain.height,
+                        |x, y| terrain.is_solid(x, y)))
+                } else {
+                    0
+                };
+                self.set_tile(layer, col, row, gid);
+            }
+        }
+    }
+
+    /// Re-resolve the terrain tile at `col`, `row`, if there is one.
+    fn resolve_autotile(&mut self, layer: usize, col: u32, row: u32, rules: &AutoTileRules) {
+        if let Some(gid) = self.tile_id(layer, col, row) {
+            if rules.contains(gid) {
+                let new_gid = rules.gid_for(self.neighbour_mask(layer, col, row, rules));
+                if new_gid != gid {
+                    self.set_tile(layer, col, row, new_gid);
+                }
+            }
+        }
+    }
+
+    /// Place (`filled`) or remove a terrain tile at `col`, `row`, then
+    /// resolve it and its neighbours to the right edge/corner variants.
+    pub fn autotile(&mut self, layer: usize, col: u32, row: u32, filled: bool, rules: &AutoTileRules) {
+        if self.set_tile(layer, col, row, if filled { rules.default } else { 0 }).is_none() {
+            return;
+        }
+        for dy in -1..2 {
+            for dx in -1..2 {
+                let (x, y) = (col as i32 + dx, row as i32 + dy);
+                if x >= 0 && y >= 0 {
+                    self.resolve_autotile(layer, x as u32, y as u32, rules);
+                }
+            }
+        }
+    }
+
+    /// Resolve every terrain tile in `layer`, e.g. after
+    /// generating the layer procedurally.
+    pub fn autotile_layer(&mut self, layer: usize, rules: &AutoTileRules) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                self.resolve_autotile(layer, col, row, rules);
+            }
+        }
+    }
+}
+
+/// Default maximum number of decals a `Map` holds at once.
+pub const DEFAULT_DECAL_CAPACITY: usize = 64;
+
+/// A temporary mark stamped into the world, such as a footprint,
+/// scorch mark or splat.
+#[derive(Clone)]
+pub struct Decal {
+    pub texture: Rc<RefCell<Texture>>,
+    pub clip_rect: Option<Rect>,
+    /// World-space position of the decal's top-left corner.
+    pub x: i32,
+    pub y: i32,
+    /// Milliseconds left before the decal disappears.
+    pub ttl: u32,
+    /// Milliseconds, at the end of its life, over which
+    /// the decal fades out.
+    pub fade: u32,
+}
+
+impl Decal {
+    pub fn new(tx: Rc<RefCell<Texture>>, cr: Option<Rect>, x: i32, y: i32, ttl: u32, fade: u32) -> Self {
+        Decal {
+            texture: tx,
+            clip_rect: cr,
+            x: x,
+            y: y,
+            ttl: ttl,
+            fade: fade,
+        }
+    }
+
+    fn alpha(&self) -> u8 {
+        if self.ttl >= self.fade {
+            255
+        } else {
+            (255 * self.ttl / self.fade) as u8
+        }
+    }
+}
+
+/// A bounded collection of decals. Once `capacity` is reached,
+/// stamping a new decal removes the oldest one.
+#[derive(Clone)]
+pub struct Decals {
+    pub capacity: usize,
+    pub decals: VecDeque<Decal>,
+}
+
+impl Decals {
+    pub fn new(capacity: usize) -> Self {
+        Decals {
+            capacity: capacity,
+            decals: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn stamp(&mut self, d: Decal) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.decals.len() >= self.capacity {
+            self.decals.pop_front();
+        }
+        self.decals.push_back(d);
+    }
+
+    pub fn clear(&mut self) {
+        self.decals.clear();
+    }
+
+    /// Age every decal by `dt` milliseconds, removing expired ones.
+    pub fn update(&mut self, dt: u32) {
+        for d in self.decals.iter_mut() {
+            d.ttl = d.ttl.saturating_sub(dt);
+        }
+        self.decals.retain(|d| d.ttl > 0);
+    }
+}
+
+impl CameraDrawable for Decals {
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        for d in self.decals.iter() {
+            let mut tx = d.texture.borrow_mut();
+            let (w, h) = match d.clip_rect {
+                Some(cr) => (cr.width(), cr.height()),
+                None => {
+                    let q = tx.query();
+                    (q.width, q.height)
+                },
+            };
+            if !c.view_rect().has_intersection(&physics::Rect::new(d.x, d.y, w, h)) { continue }
+
+            let alpha = d.alpha();
+            tx.set_alpha_mod(alpha);
+            r.copy(&tx, d.clip_rect,
+                Some(Rect::new_unwrap(d.x - c.pos.x as i32, d.y - c.pos.y as i32, w, h)));
+            tx.set_alpha_mod(255);
+        }
+    }
+}
+
+impl Map {
+    /// Draw the tile layers that are (or aren't) `foreground`.
+    fn draw_layers(&mut self, r: &mut Renderer, c: &Camera, foreground: bool) {
+        let offsets = self.wrap_offsets();
+        for l in 0..self.layers.len() {
+            self.draw_image_layers(r, c, l, foreground);
+            let (vx, vy) = {
+                let layer = &self.layers[l];
+                if !layer.visible || layer.collision || layer.foreground != foreground {
                     continue;
                 }
+                layer_view(c, (layer.offset_x, layer.offset_y), (layer.parallax_x, layer.parallax_y))
+            };
+            let view = (vx as i32, vy as i32);
+            // animated tiles are drawn over the cached ones every frame
+            let cached = self.cache_tiles && self.orientation == tiled::Orientation::Orthogonal &&
+                self.draw_cached(r, c, l, view, &offsets);
 
-                let j = j as i32;
-                let (x, y) = (j*self.tile_width as i32, i*self.tile_height as i32);
-                if (x+self.tile_width as i32) < c.pos.x as i32 || x > (c.pos.x+c.width) as i32 { continue }
-                if (y+self.tile_height as i32) < c.pos.y as i32 || y > (c.pos.y+c.height) as i32 { continue }
-                r.copy(&*tile.texture, tile.clip_rect,
-                    Some(Rect::new_unwrap(x - c.pos.x as i32, y - c.pos.y as i32,
-                        self.tile_width, self.tile_height)));
+            for ts in self.tilesets.iter() {
+                set_layer_mods(&mut ts.texture.borrow_mut(), self.layers[l].opacity, self.layers[l].tint);
             }
+            self.draw_tiles(r, c, l, view, &offsets, cached);
+            for ts in self.tilesets.iter() {
+                set_layer_mods(&mut ts.texture.borrow_mut(), 1.0, None);
+            }
+        }
+        let n = self.layers.len();
+        self.draw_image_layers(r, c, n, foreground);
+    }
+
+    /// Draw the image layers with `below` tile layers under them,
+    /// or more if `below` is the number of tile layers.
+    fn draw_image_layers(&mut self, r: &mut Renderer, c: &Camera, below: usize, foreground: bool) {
+        let last = below >= self.layers.len();
+        for il in self.image_layers.iter_mut() {
+            if il.foreground == foreground && (il.below == below || last && il.below > below) {
+                il.draw(r, c);
+            }
+        }
+    }
+
+    /// Draw the foreground layers, which go over the player
+    /// and other entities.
+    pub fn draw_foreground(&mut self, r: &mut Renderer, c: &Camera) {
+        self.draw_layers(r, c, true);
+    }
+}
+
+impl CameraDrawable for Map {
+    /// Draw everything behind the entities: the `Background`,
+    /// `Map` and `Decals` passes.
+    fn draw(&mut self, r: &mut Renderer, c: &Camera) {
+        self.draw_pass(r, c, DrawPass::Background);
+        self.draw_pass(r, c, DrawPass::Map);
+        self.draw_pass(r, c, DrawPass::Decals);
+    }
+}
+
+impl PassDrawable for Map {
+    fn draw_pass(&mut self, r: &mut Renderer, c: &Camera, pass: DrawPass) {
+        match pass {
+            DrawPass::Background => {
+                self.sky.draw(r, c);
+                self.background.draw(r, c);
+            },
+            DrawPass::Map => self.draw_layers(r, c, false),
+            DrawPass::Decals => self.decals.draw(r, c),
+            DrawPass::Foreground => self.draw_foreground(r, c),
+            DrawPass::Debug => {
+                let draw_col = r.draw_color();
+                r.set_draw_color(Color::RGB(255, 0, 0));
+                for h in self.hazards.iter() {
+                    let (x0, y0) = c.world_to_screen(h.pivot);
+                    let (x1, y1) = c.world_to_screen(h.tip());
+                    r.draw_line(Point::new(x0, y0), Point::new(x1, y1));
+                }
+                r.set_draw_color(draw_col);
+            },
+            DrawPass::Entities | DrawPass::Hud => (),
         }
     }
 }
@@ -0,0 +1,140 @@
+use std::cmp;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use tiled;
+use collision::CollisionMask;
+use map::{Map, Tileset, AutoTileRules, PlayerStart};
+use super::{Direction, Vec2};
+
+/// Name of the tile layer generated maps draw their terrain in.
+pub const TERRAIN_LAYER: &'static str = "terrain";
+
+/// Tuning for `generate`. Lengths and heights are in tiles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Params {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// Length of the platform the player starts on.
+    pub start_platform: u32,
+    pub min_platform: u32,
+    pub max_platform: u32,
+    pub min_gap: u32,
+    pub max_gap: u32,
+    /// Furthest the ground rises or drops from one platform to the next.
+    pub max_step: u32,
+    /// Range of the ground's height above the bottom of the map.
+    pub min_ground: u32,
+    pub max_ground: u32,
+    /// Chance of an item on each platform after the first.
+    pub item_chance: f64,
+}
+
+impl Params {
+    /// Tuning for a `width` x `height` map with gaps and steps
+    /// the player can clear.
+    pub fn new(width: u32, height: u32, tile_width: u32, tile_height: u32) -> Self {
+        Params {
+            width: width,
+            height: height,
+            tile_width: tile_width,
+            tile_height: tile_height,
+            start_platform: 8,
+            min_platform: 3,
+            max_platform: 10,
+            min_gap: 1,
+            max_gap: 4,
+            max_step: 2,
+            min_ground: 2,
+            max_ground: cmp::max(2, height / 2),
+            item_chance: 0.4,
+        }
+    }
+}
+
+/// A generated level, before it's turned into tiles.
+#[derive(Clone, Debug)]
+pub struct Level {
+    /// Which cells are ground.
+    pub terrain: CollisionMask,
+    /// Column and row of the cell each item sits in, on top
+    /// of a platform, for the game to place its pickups at.
+    pub items: Vec<(u32, u32)>,
+    /// Column and row of the ground cell the player starts on.
+    pub start: (u32, u32),
+}
+
+/// Generate a run of platforms separated by gaps and changes in
+/// elevation, from left to right. The same `seed` and `params`
+/// always generate the same level.
+pub fn generate(seed: u32, params: &Params) -> Level {
+    let p = params;
+    let mut rng = XorShiftRng::from_seed([0x5f3759df, 0x2545f491, 0x9e3779b9, seed]);
+    let mut terrain = CollisionMask::new(p.width, p.height);
+    let mut items = vec!();
+    let (min_ground, max_ground) = (cmp::max(1, p.min_ground), cmp::min(p.max_ground, p.height));
+    let mut ground = (min_ground + max_ground) / 2;
+    let start = (cmp::min(p.start_platform, p.width) / 2, p.height - ground);
+
+    let mut col = 0;
+    let mut first = true;
+    while col < p.width {
+        let len = if first {
+            p.start_platform
+        } else {
+            rng.gen_range(p.min_platform, p.max_platform + 1)
+        };
+        let len = cmp::max(1, cmp::min(len, p.width - col));
+        for c in col..col + len {
+            for row in p.height - ground..p.height {
+                terrain.set(c, row, true);
+            }
+        }
+        if !first && p.height > ground && rng.gen::<f64>() < p.item_chance {
+            items.push((col + len / 2, p.height - ground - 1));
+        }
+        col += len + rng.gen_range(p.min_gap, p.max_gap + 1);
+
+        let step = rng.gen_range(0, 2 * p.max_step + 1) as i64 - p.max_step as i64;
+        ground = cmp::max(min_ground as i64, cmp::min(max_ground as i64, ground as i64 + step)) as u32;
+        first = false;
+    }
+
+    Level {
+        terrain: terrain,
+        items: items,
+        start: start,
+    }
+}
+
+/// Build a map from `level`, drawing its terrain with `tileset` as
+/// resolved by `rules` in a layer named `TERRAIN_LAYER`. The player
+/// starts on `level.start`.
+pub fn build_map(level: &Level, params: &Params, tileset: &Tileset, rules: &AutoTileRules) -> Map {
+    let tmap = tiled::Map {
+        layers: vec!(),
+        width: params.width,
+        height: params.height,
+        tilesets: vec!(),
+        tilewidth: params.tile_width,
+        tileheight: params.tile_height,
+        properties: tiled::Properties::default(),
+        orientation: tiled::Orientation::Orthogonal,
+        hexsidelength: None,
+        staggeraxis: None,
+        staggerindex: None,
+        backgroundcolor: None,
+    };
+    let mut map = Map::new_from_tiled_map(&tmap);
+    map.insert_data_using_tilset(TERRAIN_LAYER, &vec![0; (params.width * params.height) as usize], tileset);
+    map.autotile_from_mask(0, &level.terrain, rules);
+
+    let (col, row) = level.start;
+    map.player_start = Some(PlayerStart {
+        pos: Vec2::new(((col * params.tile_width) + params.tile_width / 2) as f64,
+                       (row * params.tile_height) as f64),
+        facing: Direction::StillRight,
+        camera: None,
+    });
+    map
+}
@@ -0,0 +1,253 @@
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, Neg};
+use collision::CollisionMask;
+
+/// A 2D vector, used for positions, velocities and accelerations.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec2 {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Vec2 {
+    pub fn new(x: f64, y: f64) -> Self {
+        Vec2{x: x, y: y}
+    }
+
+    /// Helper method to save typing out
+    /// the zero Vec2 struct.
+    pub fn zero() -> Self {
+        Vec2{x: 0.0, y: 0.0}
+    }
+
+    pub fn length(&self) -> f64 {
+        (self.x*self.x + self.y*self.y).sqrt()
+    }
+
+    /// Returns the vector scaled to length 1, or
+    /// the zero vector if it has no length.
+    pub fn normalize(&self) -> Self {
+        let len = self.length();
+        if len == 0.0 {
+            return Vec2::zero();
+        }
+        *self / len
+    }
+
+    pub fn dot(&self, other: &Vec2) -> f64 {
+        self.x*other.x + self.y*other.y
+    }
+
+    /// Returns the vector a fraction `t` of the way from `self` to `to`.
+    pub fn lerp(&self, to: &Vec2, t: f64) -> Self {
+        *self + (*to - *self) * t
+    }
+
+    /// Returns the vector turned `degrees` clockwise, as seen
+    /// on screen with y pointing down.
+    pub fn rotate(&self, degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Vec2::new(self.x*cos - self.y*sin, self.x*sin + self.y*cos)
+    }
+
+    /// Returns the nearest whole pixel, for drawing.
+    pub fn to_pixel(&self) -> (i32, i32) {
+        (self.x.round() as i32, self.y.round() as i32)
+    }
+}
+
+impl From<(f64, f64)> for Vec2 {
+    fn from(v: (f64, f64)) -> Self {
+        Vec2::new(v.0, v.1)
+    }
+}
+
+impl From<(i32, i32)> for Vec2 {
+    fn from(v: (i32, i32)) -> Self {
+        Vec2::new(v.0 as f64, v.1 as f64)
+    }
+}
+
+impl From<Vec2> for (f64, f64) {
+    fn from(v: Vec2) -> Self {
+        (v.x, v.y)
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Vec2 {
+    fn add_assign(&mut self, other: Vec2) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Vec2 {
+    fn sub_assign(&mut self, other: Vec2) {
+        self.x -= other.x;
+        self.y -= other.y;
+    }
+}
+
+impl Mul<f64> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, k: f64) -> Vec2 {
+        Vec2::new(self.x * k, self.y * k)
+    }
+}
+
+impl MulAssign<f64> for Vec2 {
+    fn mul_assign(&mut self, k: f64) {
+        self.x *= k;
+        self.y *= k;
+    }
+}
+
+impl Div<f64> for Vec2 {
+    type Output = Vec2;
+    fn div(self, k: f64) -> Vec2 {
+        Vec2::new(self.x / k, self.y / k)
+    }
+}
+
+impl Neg for Vec2 {
+    type Output = Vec2;
+    fn neg(self) -> Vec2 {
+        Vec2::new(-self.x, -self.y)
+    }
+}
+
+/// An axis-aligned rectangle, in pixels. Its accessors mirror
+/// `sdl2::rect::Rect`'s, which it's converted to for drawing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the rect moved by `d` pixels.
+    pub fn offset(&self, d: Vec2) -> Self {
+        Rect::new((self.x as f64 + d.x) as i32, (self.y as f64 + d.y) as i32, self.width, self.height)
+    }
+
+    /// Whether the rects overlap. Rects that only touch don't.
+    pub fn has_intersection(&self, other: &Rect) -> bool {
+        let (l, r) = (self.x as i64, self.x as i64 + self.width as i64);
+        let (t, b) = (self.y as i64, self.y as i64 + self.height as i64);
+        let (ol, or) = (other.x as i64, other.x as i64 + other.width as i64);
+        let (ot, ob) = (other.y as i64, other.y as i64 + other.height as i64);
+        l < or && ol < r && t < ob && ot < b
+    }
+}
+
+/// Downward acceleration applied every tick.
+pub const GRAVITY: f64 = 9.8;
+const VELOCITY_DECAY_FACTOR_X: f64 = 0.2;
+const VELOCITY_DECAY_FACTOR_Y: f64 = 0.7;
+const VELOCITY_CUTOFF: f64 = 2.0;
+const ACCELERATION_DECAY_FACTOR_X: f64 = 0.80;
+const ACCELERATION_CUTOFF: f64 = 0.1;
+
+/// Advance a body at `pos` by one tick: move it by `v`, apply
+/// gravity and `a` to `v`, then let both decay.
+pub fn step(pos: &mut Vec2, v: &mut Vec2, a: &mut Vec2) {
+    a.y = GRAVITY;
+    *pos += *v;
+    *v += *a;
+
+    v.x *= VELOCITY_DECAY_FACTOR_X;
+    v.y *= VELOCITY_DECAY_FACTOR_Y;
+    if v.x < VELOCITY_CUTOFF && v.x > -VELOCITY_CUTOFF { v.x = 0.0; }
+    if v.y < VELOCITY_CUTOFF && v.y > -VELOCITY_CUTOFF { v.y = 0.0; }
+
+    a.x *= ACCELERATION_DECAY_FACTOR_X;
+    if a.x < ACCELERATION_CUTOFF && a.x > -ACCELERATION_CUTOFF { a.x = 0.0; }
+}
+
+/// Returns the inclusive range of `tile_width` x `tile_height` tiles
+/// `rect` covers, clamped to `mask`, as first column, first row, last
+/// column and last row. `None` if it's entirely outside the mask.
+pub fn tiles_in_rect(mask: &CollisionMask, tile_width: u32, tile_height: u32, rect: &Rect) -> Option<(u32, u32, u32, u32)> {
+    if rect.width == 0 || rect.height == 0 || mask.width == 0 || mask.height == 0 {
+        return None;
+    }
+    let (tw, th) = (tile_width as i64, tile_height as i64);
+    let (left, top) = (rect.x as i64, rect.y as i64);
+    let (right, bottom) = (left + rect.width as i64 - 1, top + rect.height as i64 - 1);
+    let first_col = if left < 0 { (left - tw + 1) / tw } else { left / tw };
+    let first_row = if top < 0 { (top - th + 1) / th } else { top / th };
+    let (last_col, last_row) = (right / tw, bottom / th);
+    if right < 0 || bottom < 0 || first_col >= mask.width as i64 || first_row >= mask.height as i64 {
+        return None;
+    }
+    let clamp = |v: i64, max: u32| if v < 0 { 0 } else if v >= max as i64 { max - 1 } else { v as u32 };
+    Some((clamp(first_col, mask.width), clamp(first_row, mask.height),
+          clamp(last_col, mask.width), clamp(last_row, mask.height)))
+}
+
+/// Whether any solid tile of `mask` overlaps `rect`.
+pub fn rect_is_solid(mask: &CollisionMask, tile_width: u32, tile_height: u32, rect: &Rect) -> bool {
+    match tiles_in_rect(mask, tile_width, tile_height, rect) {
+        Some((c0, r0, c1, r1)) => mask.area_solid(c0, r0, c1, r1),
+        None => false,
+    }
+}
+
+/// Returns the column and row of every solid tile of `mask`
+/// that overlaps `rect`.
+pub fn solid_tiles_in_rect(mask: &CollisionMask, tile_width: u32, tile_height: u32, rect: &Rect) -> Vec<(u32, u32)> {
+    let mut tiles = vec!();
+    if let Some((c0, r0, c1, r1)) = tiles_in_rect(mask, tile_width, tile_height, rect) {
+        for row in r0..r1 + 1 {
+            if !mask.row_span_solid(row, c0, c1) {
+                continue;
+            }
+            for col in c0..c1 + 1 {
+                if mask.is_solid(col, row) {
+                    tiles.push((col, row));
+                }
+            }
+        }
+    }
+    tiles
+}
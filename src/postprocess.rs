@@ -0,0 +1,222 @@
+use sdl2::rect::Rect;
+use sdl2::render::{Renderer, Texture, BlendMode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+
+/// A screen-space effect, drawn over the frame once the
+/// scene has been rendered.
+pub trait Effect {
+    /// Draw the effect. `scene` holds the frame as it was before
+    /// any effects were applied, and is `w` x `h` pixels.
+    fn apply(&mut self, r: &mut Renderer, scene: &mut Texture, w: u32, h: u32);
+
+    /// Restart the effect, for effects that play once.
+    fn trigger(&mut self) {}
+}
+
+/// Darkens every `spacing`th row of pixels, like a CRT.
+pub struct Scanlines {
+    pub spacing: u32,
+    pub alpha: u8,
+}
+
+impl Effect for Scanlines {
+    fn apply(&mut self, r: &mut Renderer, _: &mut Texture, w: u32, h: u32) {
+        r.set_draw_color(Color::RGBA(0, 0, 0, self.alpha));
+        let mut y = 0;
+        while y < h {
+            r.fill_rect(Rect::new_unwrap(0, y as i32, w, 1));
+            y += self.spacing;
+        }
+    }
+}
+
+/// Darkens the edges of the screen, fading in over
+/// `width` pixels up to `alpha` at the very edge.
+pub struct Vignette {
+    pub width: u32,
+    pub alpha: u8,
+}
+
+impl Effect for Vignette {
+    fn apply(&mut self, r: &mut Renderer, _: &mut Texture, w: u32, h: u32) {
+        for i in 0..self.width {
+            if 2*i >= w || 2*i >= h {
+                break;
+            }
+            let alpha = self.alpha as u32 * (self.width - i) / self.width;
+            r.set_draw_color(Color::RGBA(0, 0, 0, alpha as u8));
+            r.draw_rect(Rect::new_unwrap(i as i32, i as i32, w - 2*i, h - 2*i));
+        }
+    }
+}
+
+/// Approximates chromatic aberration by adding the scene's red
+/// and blue channels back in, shifted `offset` pixels apart.
+pub struct ChromaticOffset {
+    pub offset: i32,
+    pub alpha: u8,
+}
+
+impl Effect for ChromaticOffset {
+    fn apply(&mut self, r: &mut Renderer, scene: &mut Texture, w: u32, h: u32) {
+        let blend = scene.blend_mode();
+        scene.set_blend_mode(BlendMode::Add);
+        scene.set_alpha_mod(self.alpha);
+        scene.set_color_mod(255, 0, 0);
+        r.copy(scene, None, Some(Rect::new_unwrap(self.offset, 0, w, h)));
+        scene.set_color_mod(0, 0, 255);
+        r.copy(scene, None, Some(Rect::new_unwrap(-self.offset, 0, w, h)));
+        scene.set_color_mod(255, 255, 255);
+        scene.set_alpha_mod(255);
+        scene.set_blend_mode(blend);
+    }
+}
+
+/// Fills the screen with `color` when triggered, fading
+/// out over `frames` frames.
+pub struct Flash {
+    pub color: Color,
+    pub frames: u32,
+    frames_left: u32,
+}
+
+impl Flash {
+    pub fn new(color: Color, frames: u32) -> Self {
+        Flash {
+            color: color,
+            frames: frames,
+            frames_left: 0,
+        }
+    }
+}
+
+impl Effect for Flash {
+    fn apply(&mut self, r: &mut Renderer, _: &mut Texture, w: u32, h: u32) {
+        if self.frames_left == 0 {
+            return;
+        }
+        let (red, green, blue) = match self.color {
+            Color::RGB(red, green, blue) | Color::RGBA(red, green, blue, _) => (red, green, blue),
+        };
+        let alpha = 255 * self.frames_left / self.frames;
+        r.set_draw_color(Color::RGBA(red, green, blue, alpha as u8));
+        r.fill_rect(Rect::new_unwrap(0, 0, w, h));
+        self.frames_left -= 1;
+    }
+
+    fn trigger(&mut self) {
+        self.frames_left = self.frames;
+    }
+}
+
+struct ChainEntry {
+    name: String,
+    effect: Box<Effect>,
+    enabled: bool,
+}
+
+/// An ordered list of named effects. While any of them is enabled,
+/// the scene is drawn into a texture between `begin` and `end`,
+/// which then draws it to the screen and applies the effects in
+/// the order they were added.
+pub struct EffectChain {
+    effects: Vec<ChainEntry>,
+    scene: Option<Texture>,
+    size: (u32, u32),
+    /// Whether the scene texture is the current render target.
+    active: bool,
+}
+
+impl EffectChain {
+    pub fn new() -> Self {
+        EffectChain {
+            effects: vec!(),
+            scene: None,
+            size: (0, 0),
+            active: false,
+        }
+    }
+
+    /// Add `effect` to the end of the chain as `name`.
+    pub fn push(&mut self, name: &str, effect: Box<Effect>, enabled: bool) {
+        self.effects.push(ChainEntry {
+            name: name.to_string(),
+            effect: effect,
+            enabled: enabled,
+        });
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.effects.retain(|e| e.name != name);
+    }
+
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) {
+        for e in self.effects.iter_mut().filter(|e| e.name == name) {
+            e.enabled = enabled;
+        }
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.effects.iter().any(|e| e.name == name && e.enabled)
+    }
+
+    pub fn toggle(&mut self, name: &str) {
+        let enabled = self.is_enabled(name);
+        self.set_enabled(name, !enabled);
+    }
+
+    /// Restart the effect `name`, e.g. a `Flash`.
+    pub fn trigger(&mut self, name: &str) {
+        for e in self.effects.iter_mut().filter(|e| e.name == name) {
+            e.effect.trigger();
+        }
+    }
+
+    /// Redirect drawing into the scene texture, if any effect is
+    /// enabled and the renderer supports render targets.
+    pub fn begin(&mut self, r: &mut Renderer) {
+        if !self.effects.iter().any(|e| e.enabled) {
+            return;
+        }
+        let size = match r.output_size() {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+        if self.scene.is_none() || self.size != size {
+            self.scene = r.create_texture_target(PixelFormatEnum::RGBA8888, size).ok();
+            self.size = size;
+        }
+
+        let scene = match self.scene.take() {
+            Some(scene) => scene,
+            None => return,
+        };
+        match r.render_target() {
+            Some(mut rt) => self.active = rt.set(scene).is_ok(),
+            None => self.scene = Some(scene),
+        }
+    }
+
+    /// Draw the scene to the screen and apply the enabled effects.
+    pub fn end(&mut self, r: &mut Renderer) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        let mut scene = match r.render_target().map(|mut rt| rt.reset()) {
+            Some(Ok(Some(scene))) => scene,
+            _ => return,
+        };
+
+        r.copy(&scene, None, None);
+        let (draw_color, blend) = (r.draw_color(), r.blend_mode());
+        r.set_blend_mode(BlendMode::Blend);
+        let (w, h) = self.size;
+        for e in self.effects.iter_mut().filter(|e| e.enabled) {
+            e.effect.apply(r, &mut scene, w, h);
+        }
+        r.set_draw_color(draw_color);
+        r.set_blend_mode(blend);
+        self.scene = Some(scene);
+    }
+}
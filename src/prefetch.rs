@@ -0,0 +1,87 @@
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::collections::HashMap;
+use tiled;
+
+/// Name of the map property holding the path of the level that
+/// follows, relative to the map file.
+pub const NEXT_LEVEL_PROPERTY: &'static str = "next_level";
+
+/// Read a map in either format, going by the file's extension.
+pub fn read_map(path: &Path) -> Result<tiled::Map, tiled::ReadError> {
+    tiled::Map::read(path)
+}
+
+/// Reads and decodes maps on background threads, so that the next
+/// level is ready by the time the player reaches it. Textures still
+/// have to be loaded on the main thread once the map is taken.
+pub struct Prefetcher {
+    pending: HashMap<PathBuf, Receiver<Result<tiled::Map, tiled::ReadError>>>,
+}
+
+impl Prefetcher {
+    pub fn new() -> Self {
+        Prefetcher {
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Start reading the map at `path` in the background, unless
+    /// it's already being read.
+    pub fn prefetch<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_path_buf();
+        if self.pending.contains_key(&path) {
+            return;
+        }
+        let (tx, rx) = channel();
+        let p = path.clone();
+        thread::spawn(move || {
+            // the receiver is gone if the prefetch was cancelled
+            let _ = tx.send(read_map(&p));
+        });
+        self.pending.insert(path, rx);
+    }
+
+    /// Prefetch the levels named by the `next_level` property of
+    /// `map`, which was read from `map_path`.
+    pub fn prefetch_next(&mut self, map: &tiled::Map, map_path: &Path) {
+        if let Some(next) = map.properties.get_str(NEXT_LEVEL_PROPERTY) {
+            let dir = map_path.parent().unwrap_or(Path::new(""));
+            self.prefetch(dir.join(next));
+        }
+    }
+
+    pub fn is_pending<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.pending.contains_key(path.as_ref())
+    }
+
+    /// Stop waiting for the map at `path`. The thread reading it
+    /// runs to completion, but its result is dropped.
+    pub fn cancel<P: AsRef<Path>>(&mut self, path: P) {
+        self.pending.remove(path.as_ref());
+    }
+
+    /// Cancel every prefetch except those of `keep`, e.g. when the
+    /// player backtracks and the level graph's frontier changes.
+    pub fn retain(&mut self, keep: &[PathBuf]) {
+        let cancelled: Vec<PathBuf> = self.pending.keys().filter(|p| !keep.contains(p)).cloned().collect();
+        for p in cancelled {
+            self.pending.remove(&p);
+        }
+    }
+
+    /// Returns the map at `path`, waiting for it to finish if it's
+    /// being prefetched and reading it now if it isn't.
+    pub fn take<P: AsRef<Path>>(&mut self, path: P) -> Result<tiled::Map, tiled::ReadError> {
+        let path = path.as_ref();
+        match self.pending.remove(path) {
+            Some(rx) => match rx.recv() {
+                Ok(map) => map,
+                // the reading thread panicked, so try again here
+                Err(_) => read_map(path),
+            },
+            None => read_map(path),
+        }
+    }
+}
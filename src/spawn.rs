@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use tiled;
+use flags::Flags;
+
+/// Builds a game object from a Tiled object and the offset, in
+/// pixels, of the layer it's in, or returns `None` to skip it.
+pub type Constructor<T> = Box<Fn(&tiled::Object, (f64, f64)) -> Option<T>>;
+
+/// Turns the objects in a map's object layers into game objects of
+/// type `T`, using the constructor registered for each object's type.
+/// Objects whose `spawn_if` property is a condition that doesn't hold
+/// (see `Flags::check`) are skipped, as are objects of unregistered
+/// types.
+pub struct EntityFactory<T> {
+    constructors: HashMap<String, Constructor<T>>,
+}
+
+impl<T> EntityFactory<T> {
+    pub fn new() -> Self {
+        EntityFactory {
+            constructors: HashMap::new(),
+        }
+    }
+
+    /// Build objects of type `type_name` with `f`, replacing any
+    /// constructor already registered for it.
+    pub fn register<F>(&mut self, type_name: &str, f: F)
+        where F: Fn(&tiled::Object, (f64, f64)) -> Option<T> + 'static {
+        self.constructors.insert(type_name.to_string(), Box::new(f));
+    }
+
+    pub fn is_registered(&self, type_name: &str) -> bool {
+        self.constructors.contains_key(type_name)
+    }
+
+    /// Build `o`, which is in a layer offset by `offset` pixels.
+    pub fn spawn(&self, o: &tiled::Object, offset: (f64, f64), flags: &Flags) -> Option<T> {
+        if let Some(condition) = o.properties.get_str("spawn_if") {
+            if !flags.check(condition) {
+                return None;
+            }
+        }
+        self.constructors.get(&o.object_type).and_then(|f| f(o, offset))
+    }
+
+    /// Build every object in `layers`, which should be flattened,
+    /// in the order they appear.
+    pub fn spawn_all(&self, layers: &[tiled::Layer], flags: &Flags) -> Vec<T> {
+        let mut spawned = vec!();
+        for l in layers {
+            if let tiled::LayerKind::Objects(ref objects) = l.kind {
+                spawned.extend(objects.iter()
+                    .filter_map(|o| self.spawn(o, (l.offsetx, l.offsety), flags)));
+            }
+        }
+        spawned
+    }
+}
+
+/// Build every object of type `type_name` in `layers`, which should
+/// be flattened, with `f`, in the order they appear. Objects `f`
+/// returns `None` for are skipped. Unlike `EntityFactory::spawn_all`,
+/// `spawn_if` isn't checked.
+pub fn spawn_type<T, F>(layers: &[tiled::Layer], type_name: &str, f: F) -> Vec<T>
+    where F: Fn(&tiled::Object, (f64, f64)) -> Option<T> {
+    let mut spawned = vec!();
+    for l in layers {
+        if let tiled::LayerKind::Objects(ref objects) = l.kind {
+            spawned.extend(objects.iter()
+                .filter(|o| o.object_type == type_name)
+                .filter_map(|o| f(o, (l.offsetx, l.offsety))));
+        }
+    }
+    spawned
+}
@@ -0,0 +1,131 @@
+use tiled;
+use spawn;
+use physics::Rect;
+
+/// Object type of zones that drain a survival meter in Tiled object layers.
+pub const METER_ZONE_TYPE: &'static str = "meter_zone";
+
+/// How full a meter is when it's full.
+pub const METER_MAX: f64 = 100.0;
+
+/// How much a zone drains its meter per tick, unless
+/// its `drain` property says otherwise.
+pub const DEFAULT_DRAIN: f64 = 0.5;
+
+/// How much a meter recovers per tick outside its zones, unless
+/// the first zone draining it has a `recover` property.
+pub const DEFAULT_RECOVER: f64 = 2.0;
+
+/// A zone, such as water or a lava room, that drains a survival
+/// meter while the player is inside it.
+///
+/// Zones are `meter_zone` objects whose `meter` property names the
+/// meter they drain, e.g. `oxygen` or `heat`. Their `drain` and
+/// `recover` properties are how much the meter loses per tick inside
+/// the zone and regains per tick outside, out of `METER_MAX`.
+#[derive(Clone, Debug)]
+pub struct MeterZone {
+    pub name: String,
+    /// The zone, in world pixels.
+    pub rect: Rect,
+    pub meter: String,
+    pub drain: f64,
+    pub recover: f64,
+}
+
+impl MeterZone {
+    /// Build a `MeterZone` from `o`, which is in a layer offset by
+    /// `offset` pixels. `None` if it has no `meter` property.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Option<Self> {
+        let p = &o.properties;
+        let meter = match p.get_str("meter") {
+            Some(m) if !m.is_empty() => m.to_string(),
+            _ => return None,
+        };
+        Some(MeterZone {
+            name: o.name.clone(),
+            rect: Rect::new((o.x + offset.0) as i32, (o.y + offset.1) as i32,
+                (o.width as u32).max(1), (o.height as u32).max(1)),
+            meter: meter,
+            drain: p.get_float("drain").unwrap_or(DEFAULT_DRAIN),
+            recover: p.get_float("recover").unwrap_or(DEFAULT_RECOVER),
+        })
+    }
+
+    /// Every meter zone in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, METER_ZONE_TYPE, MeterZone::from_object)
+    }
+}
+
+/// A survival resource, like oxygen or heat resistance.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Meter {
+    pub name: String,
+    /// From 0 (empty) to `METER_MAX`.
+    pub value: f64,
+    pub recover: f64,
+}
+
+impl Meter {
+    pub fn is_full(&self) -> bool {
+        self.value >= METER_MAX
+    }
+
+    /// Fraction of the meter that's left, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        self.value / METER_MAX
+    }
+}
+
+/// The player's survival meters. A meter is added the first time
+/// the player enters a zone that drains it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Meters {
+    pub meters: Vec<Meter>,
+}
+
+impl Meters {
+    pub fn new() -> Self {
+        Meters {
+            meters: vec!(),
+        }
+    }
+
+    /// Drain the meters of the zones in `zones` that `player`
+    /// overlaps, and let the others recover, for one tick.
+    /// Returns whether a meter ran out.
+    pub fn update(&mut self, zones: &[MeterZone], player: &Rect) -> bool {
+        for z in zones.iter().filter(|z| z.rect.has_intersection(player)) {
+            if !self.meters.iter().any(|m| m.name == z.meter) {
+                self.meters.push(Meter {
+                    name: z.meter.clone(),
+                    value: METER_MAX,
+                    recover: z.recover,
+                });
+            }
+        }
+
+        let mut empty = false;
+        for m in self.meters.iter_mut() {
+            let drain: f64 = zones.iter()
+                .filter(|z| z.meter == m.name && z.rect.has_intersection(player))
+                .map(|z| z.drain)
+                .sum();
+            if drain > 0.0 {
+                m.value = (m.value - drain).max(0.0);
+                empty = empty || m.value == 0.0;
+            } else {
+                m.value = (m.value + m.recover).min(METER_MAX);
+            }
+        }
+        empty
+    }
+
+    /// Fill every meter.
+    pub fn refill(&mut self) {
+        for m in self.meters.iter_mut() {
+            m.value = METER_MAX;
+        }
+    }
+}
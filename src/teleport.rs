@@ -0,0 +1,197 @@
+use tiled;
+use spawn;
+use physics::{Vec2, Rect};
+
+/// Object type of teleporters in Tiled object layers.
+pub const TELEPORTER_TYPE: &'static str = "teleporter";
+
+/// Ticks before the player can teleport again, unless
+/// the teleporter's `cooldown` property says otherwise.
+pub const DEFAULT_COOLDOWN: u32 = 30;
+
+/// Post-processing effect triggered when the player arrives, unless
+/// the teleporter's `effect` property names another one.
+pub const DEFAULT_EFFECT: &'static str = "teleport";
+
+/// What happens to the player's velocity when they teleport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VelocityMode {
+    Keep,
+    Stop,
+    /// Reverse the horizontal component.
+    MirrorX,
+    /// Reverse the vertical component.
+    MirrorY,
+    /// Exchange the horizontal and vertical components, for
+    /// portals that turn a fall into a sideways launch.
+    Swap,
+}
+
+impl VelocityMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "keep" => Some(VelocityMode::Keep),
+            "stop" => Some(VelocityMode::Stop),
+            "mirror_x" => Some(VelocityMode::MirrorX),
+            "mirror_y" => Some(VelocityMode::MirrorY),
+            "swap" => Some(VelocityMode::Swap),
+            _ => None,
+        }
+    }
+
+    /// Returns velocity `v` remapped by this mode.
+    pub fn apply(&self, v: Vec2) -> Vec2 {
+        match *self {
+            VelocityMode::Keep => v,
+            VelocityMode::Stop => Vec2::zero(),
+            VelocityMode::MirrorX => Vec2::new(-v.x, v.y),
+            VelocityMode::MirrorY => Vec2::new(v.x, -v.y),
+            VelocityMode::Swap => Vec2::new(v.y, v.x),
+        }
+    }
+}
+
+/// How the camera gets to the player after they teleport.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraMode {
+    /// Jump straight to the destination.
+    Snap,
+    /// Scroll to the destination over this many ticks.
+    Pan(u32),
+}
+
+/// A zone that sends the player to its partner teleporter.
+///
+/// Teleporters are `teleporter` objects whose `target` property
+/// names their partner. Without `target`, a teleporter pairs with
+/// the other teleporter of the same name. Further properties are
+/// `velocity` (`keep`, `stop`, `mirror_x`, `mirror_y` or `swap`),
+/// `camera` (`snap` or `pan`), `pan_ticks`, `cooldown` (ticks)
+/// and `effect`, the post-processing effect triggered on arrival
+/// (none if empty).
+#[derive(Clone, Debug)]
+pub struct Teleporter {
+    pub name: String,
+    pub target: Option<String>,
+    /// The zone, in world pixels.
+    pub rect: Rect,
+    pub velocity: VelocityMode,
+    pub camera: CameraMode,
+    pub cooldown: u32,
+    pub effect: Option<String>,
+}
+
+impl Teleporter {
+    /// Build a `Teleporter` from `o`, which is in a layer
+    /// offset by `offset` pixels.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Self {
+        let p = &o.properties;
+        let camera = match p.get_str("camera") {
+            Some("pan") => CameraMode::Pan(p.get_int("pan_ticks").unwrap_or(15).max(1) as u32),
+            _ => CameraMode::Snap,
+        };
+        Teleporter {
+            name: o.name.clone(),
+            target: p.get_str("target").map(|t| t.to_string()),
+            rect: Rect::new((o.x + offset.0) as i32, (o.y + offset.1) as i32,
+                (o.width as u32).max(1), (o.height as u32).max(1)),
+            velocity: p.get_str("velocity").and_then(VelocityMode::parse).unwrap_or(VelocityMode::Keep),
+            camera: camera,
+            cooldown: p.get_int("cooldown").map_or(DEFAULT_COOLDOWN, |c| c.max(0) as u32),
+            effect: match p.get_str("effect").unwrap_or(DEFAULT_EFFECT) {
+                "" => None,
+                e => Some(e.to_string()),
+            },
+        }
+    }
+
+    /// Every teleporter in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, TELEPORTER_TYPE, |o, offset| Some(Teleporter::from_object(o, offset)))
+    }
+
+    /// Where to put an entity with collision rect `cr` so that it
+    /// stands centred on the bottom of the zone.
+    pub fn arrival_pos(&self, cr: &Rect) -> Vec2 {
+        let x = self.rect.x() as f64 + self.rect.width() as f64/2.0 - cr.width() as f64/2.0 - cr.x() as f64;
+        let y = self.rect.y() as f64 + self.rect.height() as f64 - cr.height() as f64 - cr.y() as f64;
+        Vec2::new(x, y)
+    }
+}
+
+/// Returns the index in `teleporters` of the partner of
+/// `teleporters[i]`, if it has one.
+pub fn partner(teleporters: &[Teleporter], i: usize) -> Option<usize> {
+    let t = &teleporters[i];
+    match t.target {
+        Some(ref target) => teleporters.iter().position(|o| &o.name == target),
+        None => teleporters.iter().enumerate()
+            .position(|(j, o)| j != i && o.name == t.name),
+    }
+}
+
+/// The player's teleporting state, kept between ticks.
+pub struct TeleportState {
+    /// Ticks until the player can teleport again.
+    pub cooldown: u32,
+    /// The teleporter the player arrived at, which won't send them
+    /// back until they've stepped off it.
+    pub arrived_at: Option<usize>,
+    /// The camera's position before a panning teleport, and how
+    /// many of the pan's ticks have passed out of how many.
+    pub pan: Option<(Vec2, u32, u32)>,
+}
+
+impl TeleportState {
+    pub fn new() -> Self {
+        TeleportState {
+            cooldown: 0,
+            arrived_at: None,
+            pan: None,
+        }
+    }
+}
+
+/// Object type of warps in Tiled object layers.
+pub const WARP_TYPE: &'static str = "warp";
+
+/// A zone, such as a door, that sends the player to another map.
+///
+/// Warps are `warp` objects whose `map` property is the path of the
+/// map to load, relative to the current map's file. Their `target`
+/// property names the `player_start` object in that map to arrive
+/// at; without it the player arrives at the map's first one. Spawn
+/// points shouldn't be inside warps, or the player is sent straight
+/// back.
+#[derive(Clone, Debug)]
+pub struct Warp {
+    pub name: String,
+    /// The zone, in world pixels.
+    pub rect: Rect,
+    pub map: String,
+    pub target: Option<String>,
+}
+
+impl Warp {
+    /// Build a `Warp` from `o`, which is in a layer offset by
+    /// `offset` pixels. `None` if it has no `map` property.
+    pub fn from_object(o: &tiled::Object, offset: (f64, f64)) -> Option<Self> {
+        let p = &o.properties;
+        let map = match p.get_str("map") {
+            Some(m) if !m.is_empty() => m.to_string(),
+            _ => return None,
+        };
+        Some(Warp {
+            name: o.name.clone(),
+            rect: Rect::new((o.x + offset.0) as i32, (o.y + offset.1) as i32,
+                (o.width as u32).max(1), (o.height as u32).max(1)),
+            map: map,
+            target: p.get_str("target").map(|t| t.to_string()),
+        })
+    }
+
+    /// Every warp in `layers`, which should be flattened.
+    pub fn from_layers(layers: &[tiled::Layer]) -> Vec<Self> {
+        spawn::spawn_type(layers, WARP_TYPE, Warp::from_object)
+    }
+}
@@ -1,17 +1,125 @@
 use std;
-use std::path::Path;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::result::Result;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::str::FromStr;
 use std::string::FromUtf8Error;
-use rustc_serialize::json;
+use std::collections::HashMap;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::de::{self, Visitor, SeqAccess, MapAccess, DeserializeOwned};
+use serde_json;
+use serde_json::Value;
+use base64;
+use flate2::read::{ZlibDecoder, GzDecoder};
+use zstd;
+use xml;
+use xml::reader::{EventReader, XmlEvent};
+
+/// Flags stored in the top bits of a GID in layer data.
+pub const FLIPPED_HORIZONTALLY: u32 = 0x80000000;
+pub const FLIPPED_VERTICALLY: u32 = 0x40000000;
+pub const FLIPPED_DIAGONALLY: u32 = 0x20000000;
+/// Rotation by 120 degrees, for hexagonal maps.
+pub const ROTATED_HEXAGONAL_120: u32 = 0x10000000;
+/// Every flag bit of a GID.
+pub const GID_FLAGS: u32 = 0xf0000000;
 
 #[derive(Debug)]
 pub enum ReadError {
     IoError(std::io::Error),
     StringError(FromUtf8Error),
-    JsonError(json::DecoderError),
+    JsonError(serde_json::Error),
+    XmlError(xml::reader::Error),
+    Base64Error(base64::DecodeError),
+    /// The document was well-formed but didn't describe
+    /// a map we understand.
+    ParseError(String),
+    /// An image the map uses couldn't be loaded.
+    ImageError(String),
+    /// `error` happened while reading the file at `path`.
+    InFile(PathBuf, Box<ReadError>),
+}
+
+impl ReadError {
+    /// Attach `path` to the error, unless it already names the
+    /// file it happened in, e.g. a tileset read by a map.
+    pub fn in_file(self, path: &Path) -> ReadError {
+        match self {
+            ReadError::InFile(..) => self,
+            e => ReadError::InFile(path.to_path_buf(), Box::new(e)),
+        }
+    }
+
+    /// Returns the path of the file the error happened in, if known.
+    pub fn path(&self) -> Option<&Path> {
+        match *self {
+            ReadError::InFile(ref path, _) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the line and column of a JSON error.
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match *self {
+            ReadError::JsonError(ref e) => Some((e.line(), e.column())),
+            ReadError::InFile(_, ref e) => e.location(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadError::IoError(ref e) => write!(f, "{}", e),
+            ReadError::StringError(ref e) => write!(f, "invalid UTF-8: {}", e),
+            // serde_json's messages include the line and column
+            ReadError::JsonError(ref e) => write!(f, "invalid JSON: {}", e),
+            ReadError::XmlError(ref e) => write!(f, "invalid XML: {}", e),
+            ReadError::Base64Error(ref e) => write!(f, "invalid base64 layer data: {}", e),
+            ReadError::ParseError(ref s) => write!(f, "{}", s),
+            ReadError::ImageError(ref s) => write!(f, "couldn't load image: {}", s),
+            ReadError::InFile(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for ReadError {
+    fn description(&self) -> &str {
+        match *self {
+            ReadError::IoError(ref e) => e.description(),
+            ReadError::StringError(ref e) => e.description(),
+            ReadError::JsonError(ref e) => e.description(),
+            ReadError::XmlError(ref e) => e.description(),
+            ReadError::Base64Error(ref e) => e.description(),
+            ReadError::ParseError(ref s) => s,
+            ReadError::ImageError(ref s) => s,
+            ReadError::InFile(_, ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ReadError::IoError(ref e) => Some(e),
+            ReadError::StringError(ref e) => Some(e),
+            ReadError::JsonError(ref e) => Some(e),
+            ReadError::XmlError(ref e) => Some(e),
+            ReadError::Base64Error(ref e) => Some(e),
+            ReadError::ParseError(_) => None,
+            ReadError::ImageError(_) => None,
+            ReadError::InFile(_, ref e) => Some(&**e),
+        }
+    }
+}
+
+/// Read the JSON file at `path` as a `T`.
+fn read_json_file<T: DeserializeOwned>(path: &Path) -> Result<T, ReadError> {
+    let f = try!(File::open(path).map_err(|e| ReadError::from(e).in_file(path)));
+    serde_json::from_reader(BufReader::new(f)).map_err(|e| ReadError::from(e).in_file(path))
 }
 
 impl<'a> From<std::io::Error> for ReadError {
@@ -26,33 +134,1234 @@ impl<'a> From<FromUtf8Error> for ReadError {
     }
 }
 
-impl<'a> From<json::DecoderError> for ReadError {
-    fn from(e: json::DecoderError) -> ReadError {
+impl<'a> From<serde_json::Error> for ReadError {
+    fn from(e: serde_json::Error) -> ReadError {
         ReadError::JsonError(e)
     }
 }
 
-#[derive(RustcDecodable, RustcEncodable, Clone, Debug)]
+impl<'a> From<xml::reader::Error> for ReadError {
+    fn from(e: xml::reader::Error) -> ReadError {
+        ReadError::XmlError(e)
+    }
+}
+
+impl<'a> From<base64::DecodeError> for ReadError {
+    fn from(e: base64::DecodeError) -> ReadError {
+        ReadError::Base64Error(e)
+    }
+}
+
+/// An error writing a map with `Map::write_json`.
+#[derive(Debug)]
+pub enum WriteError {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteError::IoError(ref e) => write!(f, "{}", e),
+            WriteError::JsonError(ref e) => write!(f, "couldn't write JSON: {}", e),
+        }
+    }
+}
+
+impl Error for WriteError {
+    fn description(&self) -> &str {
+        match *self {
+            WriteError::IoError(ref e) => e.description(),
+            WriteError::JsonError(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            WriteError::IoError(ref e) => Some(e),
+            WriteError::JsonError(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(e: std::io::Error) -> WriteError {
+        WriteError::IoError(e)
+    }
+}
+
+impl From<serde_json::Error> for WriteError {
+    fn from(e: serde_json::Error) -> WriteError {
+        WriteError::JsonError(e)
+    }
+}
+
+/// Minimal in-memory XML element, built from the `xml-rs`
+/// event stream so that TMX documents can be walked as a tree.
+#[derive(Clone)]
+struct XmlElement {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl XmlElement {
+    /// Parse the root element of the XML document in `r`.
+    fn read<R: Read>(r: R) -> Result<Self, ReadError> {
+        let mut stack: Vec<XmlElement> = vec!();
+        for e in EventReader::new(r) {
+            match try!(e) {
+                XmlEvent::StartElement{name, attributes, ..} => {
+                    stack.push(XmlElement {
+                        name: name.local_name,
+                        attributes: attributes.into_iter()
+                            .map(|a| (a.name.local_name, a.value))
+                            .collect(),
+                        children: vec!(),
+                        text: String::new(),
+                    });
+                },
+                XmlEvent::EndElement{..} => {
+                    let el = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(el),
+                        None => return Ok(el),
+                    }
+                },
+                XmlEvent::Characters(s) | XmlEvent::CData(s) => {
+                    if let Some(el) = stack.last_mut() {
+                        el.text.push_str(&s);
+                    }
+                },
+                _ => (),
+            }
+        }
+        Err(ReadError::ParseError("unexpected end of XML document".to_string()))
+    }
+
+    fn open(path: &Path) -> Result<Self, ReadError> {
+        let f = try!(File::open(path).map_err(|e| ReadError::from(e).in_file(path)));
+        XmlElement::read(BufReader::new(f)).map_err(|e| e.in_file(path))
+    }
+
+    /// Parse the attribute `name`, failing if it is missing.
+    fn attr<T: FromStr>(&self, name: &str) -> Result<T, ReadError> {
+        match self.attributes.get(name) {
+            Some(v) => v.parse().map_err(|_| ReadError::ParseError(
+                format!("invalid value {:?} for attribute `{}` of <{}>", v, name, self.name))),
+            None => Err(ReadError::ParseError(
+                format!("<{}> is missing attribute `{}`", self.name, name))),
+        }
+    }
+
+    /// Parse the attribute `name`, using `default` if it is missing.
+    fn attr_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, ReadError> {
+        if self.attributes.contains_key(name) {
+            self.attr(name)
+        } else {
+            Ok(default)
+        }
+    }
+
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Merge the object template (`.tx` file, relative to `dir`) that
+    /// each `<object template="...">` in the tree refers to into the
+    /// object. The object's own attributes, shape and properties
+    /// take precedence over the template's.
+    fn apply_templates(&mut self, dir: &Path, cache: &mut HashMap<PathBuf, XmlElement>) -> Result<(), ReadError> {
+        for c in self.children.iter_mut() {
+            try!(c.apply_templates(dir, cache));
+        }
+        if self.name != "object" {
+            return Ok(());
+        }
+        let source = match self.attributes.get("template") {
+            Some(t) => dir.join(t),
+            None => return Ok(()),
+        };
+        if !cache.contains_key(&source) {
+            let tx = try!(XmlElement::open(&source));
+            let object = try!(tx.children.into_iter().find(|c| c.name == "object").ok_or(ReadError::ParseError(
+                format!("template {:?} has no <object>", source))));
+            cache.insert(source.clone(), object);
+        }
+
+        let template = &cache[&source];
+        for (k, v) in &template.attributes {
+            self.attributes.entry(k.clone()).or_insert_with(|| v.clone());
+        }
+        for tc in &template.children {
+            let own = match self.children.iter().position(|c| c.name == tc.name) {
+                Some(i) => i,
+                None => {
+                    self.children.push(tc.clone());
+                    continue;
+                },
+            };
+            if tc.name == "properties" {
+                let props = &mut self.children[own];
+                for p in &tc.children {
+                    if !props.children.iter().any(|o| o.attributes.get("name") == p.attributes.get("name")) {
+                        props.children.push(p.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Tileset {
+    #[serde(default)]
     pub firstgid: u32,
+    /// Path of an external tileset file, relative to the map. Once
+    /// the map is read, the tileset has been loaded from it and this
+    /// is `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Path of the tileset's image, relative to the map.
+    #[serde(default)]
     pub image: String,
+    #[serde(default)]
     pub imagewidth: u32,
+    #[serde(default)]
     pub imageheight: u32,
+    #[serde(default)]
     pub tileheight: u32,
+    #[serde(default)]
     pub tilewidth: u32,
+    #[serde(default)]
     pub tilecount: u32,
+    /// Number of tiles in a row of the image. 0 if the file
+    /// doesn't say, as with older versions of Tiled.
+    #[serde(default)]
+    pub columns: u32,
+    #[serde(default)]
     pub margin: u32,
+    #[serde(default)]
     pub spacing: u32,
+    /// Per-tile data, only present for tiles that have any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiles: Option<Vec<Tile>>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wangsets: Option<Vec<WangSet>>,
+    /// Terrain types, as written by Tiled before 1.5 replaced
+    /// them with Wang sets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terrains: Option<Vec<Terrain>>,
+}
+
+/// Extra data attached to a single tile of a `Tileset`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tile {
+    /// The tile's id, local to its tileset.
+    pub id: u32,
+    /// The tile's `type` field, called `class` since Tiled 1.9.
+    #[serde(rename = "type", alias = "class", default, skip_serializing_if = "String::is_empty")]
+    pub tile_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub animation: Option<Vec<Frame>>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    /// Collision shapes drawn in Tiled's tile collision editor,
+    /// as an `objectgroup` layer in the tile's coordinates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub objectgroup: Option<Layer>,
+    /// Index into the tileset's `terrains` of the terrain at each
+    /// corner: top-left, top-right, bottom-left, bottom-right.
+    /// -1 means no terrain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<Vec<i32>>,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Clone, Debug)]
+/// A frame of a tile animation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Frame {
+    /// The local id of the tile shown during this frame.
+    pub tileid: u32,
+    /// How long the frame lasts, in milliseconds.
+    pub duration: u32,
+}
+
+impl Tile {
+    /// Build a `Tile` from a TMX `<tile>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        let animation = match el.child("animation") {
+            Some(a) => {
+                let mut frames = vec!();
+                for f in a.children.iter().filter(|f| f.name == "frame") {
+                    frames.push(Frame {
+                        tileid: try!(f.attr("tileid")),
+                        duration: try!(f.attr("duration")),
+                    });
+                }
+                Some(frames)
+            },
+            None => None,
+        };
+
+        Ok(Tile {
+            id: try!(el.attr("id")),
+            tile_type: try!(el.attr_or("type", try!(el.attr_or("class", String::new())))),
+            animation: animation,
+            properties: try!(Properties::from_tmx(el)),
+            objectgroup: match el.child("objectgroup") {
+                Some(og) => Some(try!(Layer::from_tmx(og))),
+                None => None,
+            },
+            terrain: match el.attributes.get("terrain") {
+                // empty corners have no terrain, e.g. "0,0,,1"
+                Some(t) => Some(try!(t.split(',')
+                    .map(|c| if c.is_empty() { Ok(-1) } else { c.parse().map_err(|_| ReadError::ParseError(
+                        format!("invalid terrain {:?}", t))) })
+                    .collect())),
+                None => None,
+            },
+        })
+    }
+}
+
+/// A terrain type of a `Tileset`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Terrain {
+    #[serde(default)]
+    pub name: String,
+    /// Local id of the tile representing the terrain, or -1.
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+}
+
+/// A set of tiles labelled with which "color" (terrain) each of
+/// their corners and/or edges has, used for terrain transitions.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WangSet {
+    #[serde(default)]
+    pub name: String,
+    /// `corner`, `edge` or `mixed`.
+    #[serde(rename = "type", default = "default_wang_type")]
+    pub wang_type: String,
+    /// Local id of the tile representing the set, or -1.
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+    #[serde(default)]
+    pub colors: Vec<WangColor>,
+    #[serde(default)]
+    pub wangtiles: Vec<WangTile>,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WangColor {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_color")]
+    pub color: Color,
+    /// Local id of the tile representing the color, or -1.
+    #[serde(default = "default_tile")]
+    pub tile: i32,
+    #[serde(default = "default_one")]
+    pub probability: f64,
+}
+
+/// The colors of a tile's edges and corners.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WangTile {
+    pub tileid: u32,
+    /// The color of the top edge, top-right corner, right edge,
+    /// and so on clockwise, each an index into the set's
+    /// `colors` plus one. 0 means unset.
+    pub wangid: Vec<u8>,
+}
+
+impl WangSet {
+    /// Build a `WangSet` from a TMX `<wangset>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        let mut colors = vec!();
+        for c in el.children.iter().filter(|c| c.name == "wangcolor") {
+            let color: String = try!(c.attr("color"));
+            colors.push(WangColor {
+                name: try!(c.attr_or("name", String::new())),
+                color: try!(Color::parse(&color).ok_or(ReadError::ParseError(
+                    format!("invalid wang color {:?}", color)))),
+                tile: try!(c.attr_or("tile", -1)),
+                probability: try!(c.attr_or("probability", 1.0)),
+            });
+        }
+
+        let mut wangtiles = vec!();
+        for t in el.children.iter().filter(|t| t.name == "wangtile") {
+            let wangid: String = try!(t.attr("wangid"));
+            wangtiles.push(WangTile {
+                tileid: try!(t.attr("tileid")),
+                wangid: try!(wangid.split(',').map(|c| c.trim().parse().map_err(|_| ReadError::ParseError(
+                    format!("invalid wangid {:?}", wangid)))).collect()),
+            });
+        }
+
+        Ok(WangSet {
+            name: try!(el.attr_or("name", String::new())),
+            wang_type: try!(el.attr_or("type", "corner".to_string())),
+            tile: try!(el.attr_or("tile", -1)),
+            colors: colors,
+            wangtiles: wangtiles,
+            properties: try!(Properties::from_tmx(el)),
+        })
+    }
+
+    /// Returns the wangid of the local tile `id`, if it's in the set.
+    pub fn wangid_of(&self, id: u32) -> Option<&[u8]> {
+        self.wangtiles.iter().find(|t| t.tileid == id).map(|t| &t.wangid[..])
+    }
+
+    /// Returns the local id of a tile whose wangid matches `wangid`,
+    /// where 0 in `wangid` matches any color.
+    pub fn tile_for(&self, wangid: &[u8]) -> Option<u32> {
+        self.wangtiles.iter()
+            .find(|t| t.wangid.len() == wangid.len() &&
+                t.wangid.iter().zip(wangid).all(|(&a, &b)| b == 0 || a == b))
+            .map(|t| t.tileid)
+    }
+}
+
+/// Decode base64 layer data, decompressing it according to
+/// `compression`, into the little-endian `u32` GIDs it holds.
+fn decode_base64_data(data: &str, compression: Option<&str>) -> Result<Vec<u32>, ReadError> {
+    let bytes = try!(base64::decode(data.trim()));
+    let raw = match compression {
+        None => bytes,
+        Some("zlib") => {
+            let mut raw = vec!();
+            try!(ZlibDecoder::new(&bytes[..]).read_to_end(&mut raw));
+            raw
+        },
+        Some("gzip") => {
+            let mut raw = vec!();
+            try!(try!(GzDecoder::new(&bytes[..])).read_to_end(&mut raw));
+            raw
+        },
+        Some("zstd") => try!(zstd::decode_all(&bytes[..])),
+        Some(c) => return Err(ReadError::ParseError(format!("unsupported layer compression `{}`", c))),
+    };
+
+    if raw.len() % 4 != 0 {
+        return Err(ReadError::ParseError("layer data length isn't a multiple of 4".to_string()));
+    }
+    Ok(raw.chunks(4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        .collect())
+}
+
+// Defaults of JSON fields that Tiled leaves out when they have
+// their usual value.
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_tile() -> i32 {
+    -1
+}
+
+fn default_color() -> Color {
+    Color { r: 0, g: 0, b: 0, a: 255 }
+}
+
+fn default_wang_type() -> String {
+    "corner".to_string()
+}
+
+fn default_property_type() -> String {
+    "string".to_string()
+}
+
+fn default_layer_type() -> String {
+    "tilelayer".to_string()
+}
+
+fn default_font_family() -> String {
+    "sans-serif".to_string()
+}
+
+fn default_pixel_size() -> u32 {
+    16
+}
+
+fn default_halign() -> String {
+    "left".to_string()
+}
+
+fn default_valign() -> String {
+    "top".to_string()
+}
+
+/// An RGBA color, as written by Tiled (`#AARRGGBB` or `#RRGGBB`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    /// Parse a `#AARRGGBB` or `#RRGGBB` color. The leading `#`
+    /// is optional.
+    pub fn parse(s: &str) -> Option<Color> {
+        let s = s.trim_left_matches('#');
+        let v = match u32::from_str_radix(s, 16) {
+            Ok(v) => v,
+            Err(_) => return None,
+        };
+        let a = match s.len() {
+            6 => 255,
+            8 => (v >> 24) as u8,
+            _ => return None,
+        };
+        Some(Color { r: (v >> 16) as u8, g: (v >> 8) as u8, b: v as u8, a: a })
+    }
+
+    /// Multiply each component of two colors, as when
+    /// tinting an already tinted image.
+    pub fn multiply(&self, other: &Color) -> Color {
+        let mul = |a: u8, b: u8| (a as u32 * b as u32 / 255) as u8;
+        Color { r: mul(self.r, other.r), g: mul(self.g, other.g), b: mul(self.b, other.b), a: mul(self.a, other.a) }
+    }
+
+}
+
+/// Formats the color as `#AARRGGBB`.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}{:02x}", self.a, self.r, self.g, self.b)
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = try!(String::deserialize(d));
+        Color::parse(&s).ok_or_else(|| de::Error::custom(format!("invalid color {:?}", s)))
+    }
+}
+
+/// The value of a custom property.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Color(Color),
+    /// A path, relative to the file the property was read from.
+    File(String),
+    /// The id of another object in the map.
+    Object(u32),
+    /// A property of a type this crate doesn't read, such as a
+    /// Tiled 1.8 `class`, kept as found so it can be written back.
+    /// TMX values are kept as strings.
+    Other { ty: String, value: Value },
+}
+
+impl PropertyValue {
+    /// Parse `value` as a property of type `ty`, as found in TMX files.
+    fn parse(ty: &str, value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid {} property value {:?}", ty, value);
+        match ty {
+            "string" => Ok(PropertyValue::String(value.to_string())),
+            "file" => Ok(PropertyValue::File(value.to_string())),
+            "bool" => value.parse().map(PropertyValue::Bool).map_err(|_| invalid()),
+            "int" => value.parse().map(PropertyValue::Int).map_err(|_| invalid()),
+            "float" => value.parse().map(PropertyValue::Float).map_err(|_| invalid()),
+            "object" => value.parse().map(PropertyValue::Object).map_err(|_| invalid()),
+            // an unset color property is written as an empty string
+            "color" if value.is_empty() => Ok(PropertyValue::Color(Color { r: 0, g: 0, b: 0, a: 0 })),
+            "color" => Color::parse(value).map(PropertyValue::Color).ok_or_else(invalid),
+            t => Ok(PropertyValue::Other { ty: t.to_string(), value: Value::String(value.to_string()) }),
+        }
+    }
+
+    /// Convert a JSON property value of type `ty`. Values written
+    /// as strings are parsed as in TMX files.
+    fn from_json(ty: &str, value: &Value) -> Result<Self, String> {
+        if let Some(s) = value.as_str() {
+            return PropertyValue::parse(ty, s);
+        }
+        let invalid = || format!("invalid {} property value {}", ty, value);
+        match ty {
+            "bool" => value.as_bool().map(PropertyValue::Bool).ok_or_else(invalid),
+            "int" => value.as_i64().map(PropertyValue::Int).ok_or_else(invalid),
+            "float" => value.as_f64().map(PropertyValue::Float).ok_or_else(invalid),
+            "object" => value.as_u64().map(|id| PropertyValue::Object(id as u32)).ok_or_else(invalid),
+            "string" | "file" | "color" => Err(invalid()),
+            t => Ok(PropertyValue::Other { ty: t.to_string(), value: value.clone() }),
+        }
+    }
+
+    /// Returns the property's type and value, as written in JSON.
+    fn to_json(&self) -> (&str, Value) {
+        match *self {
+            PropertyValue::Bool(b) => ("bool", Value::Bool(b)),
+            PropertyValue::Int(i) => ("int", Value::from(i)),
+            PropertyValue::Float(f) => ("float", Value::from(f)),
+            PropertyValue::String(ref s) => ("string", Value::String(s.clone())),
+            PropertyValue::Color(c) => ("color", Value::String(c.to_string())),
+            PropertyValue::File(ref s) => ("file", Value::String(s.clone())),
+            PropertyValue::Object(id) => ("object", Value::from(id)),
+            PropertyValue::Other { ref ty, ref value } => (&ty[..], value.clone()),
+        }
+    }
+
+    /// Guess the type of a property from its JSON value, for maps
+    /// written before Tiled 1.2 stored the types.
+    fn infer(value: Value) -> Self {
+        match value {
+            Value::Bool(b) => PropertyValue::Bool(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => PropertyValue::Int(i),
+                None => PropertyValue::Float(n.as_f64().unwrap_or(0.0)),
+            },
+            Value::String(s) => PropertyValue::String(s),
+            v => PropertyValue::String(v.to_string()),
+        }
+    }
+}
+
+/// Custom properties attached to a map, layer, tile or object,
+/// keyed by name.
+///
+/// Maps written before Tiled 1.2 may hold values of any type as
+/// strings, so the typed getters parse string values too.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Properties(pub HashMap<String, PropertyValue>);
+
+impl Properties {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PropertyValue> {
+        self.0.get(name)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name) {
+            Some(&PropertyValue::Bool(b)) => Some(b),
+            Some(&PropertyValue::String(ref s)) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Get an int property. Float properties are truncated.
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name) {
+            Some(&PropertyValue::Int(i)) => Some(i),
+            Some(&PropertyValue::Float(f)) => Some(f as i64),
+            Some(&PropertyValue::String(ref s)) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Get a float property. Int properties are converted.
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name) {
+            Some(&PropertyValue::Float(f)) => Some(f),
+            Some(&PropertyValue::Int(i)) => Some(i as f64),
+            Some(&PropertyValue::String(ref s)) => s.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Get a string or file property.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name) {
+            Some(&PropertyValue::String(ref s)) | Some(&PropertyValue::File(ref s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn get_color(&self, name: &str) -> Option<Color> {
+        match self.get(name) {
+            Some(&PropertyValue::Color(c)) => Some(c),
+            Some(&PropertyValue::String(ref s)) => Color::parse(s),
+            _ => None,
+        }
+    }
+
+    /// Read the `<properties>` child of a TMX element, if it has one.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        let mut properties = HashMap::new();
+        if let Some(props) = el.child("properties") {
+            for p in props.children.iter().filter(|p| p.name == "property") {
+                // multi-line values are stored as the element's text
+                let value = match p.attributes.get("value") {
+                    Some(v) => &v[..],
+                    None => &p.text[..],
+                };
+                let ty = p.attributes.get("type").map(|t| &t[..]).unwrap_or("string");
+                let value = try!(PropertyValue::parse(ty, value).map_err(ReadError::ParseError));
+                properties.insert(try!(p.attr("name")), value);
+            }
+        }
+        Ok(Properties(properties))
+    }
+}
+
+/// A property in the array written by Tiled 1.2 and later.
+#[derive(Serialize, Deserialize)]
+struct JsonProperty {
+    name: String,
+    #[serde(rename = "type", default = "default_property_type")]
+    ty: String,
+    value: Value,
+}
+
+/// Accepts both the `[{"name", "type", "value"}, ...]` array written
+/// by Tiled 1.2 and later and the `{"name": value}` object written
+/// before it.
+impl<'de> Deserialize<'de> for Properties {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_any(PropertiesVisitor)
+    }
+}
+
+/// Writes the array format, sorted by name.
+impl Serialize for Properties {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        let mut seq = try!(s.serialize_seq(Some(names.len())));
+        for name in names {
+            let (ty, value) = self.0[name].to_json();
+            try!(seq.serialize_element(&JsonProperty {
+                name: name.clone(),
+                ty: ty.to_string(),
+                value: value,
+            }));
+        }
+        seq.end()
+    }
+}
+
+struct PropertiesVisitor;
+
+impl<'de> Visitor<'de> for PropertiesVisitor {
+    type Value = Properties;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an array or object of properties")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Properties, E> {
+        Ok(Properties::default())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Properties, A::Error> {
+        let mut properties = HashMap::new();
+        while let Some(p) = try!(seq.next_element::<JsonProperty>()) {
+            let value = try!(PropertyValue::from_json(&p.ty, &p.value).map_err(<A::Error as de::Error>::custom));
+            properties.insert(p.name, value);
+        }
+        Ok(Properties(properties))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Properties, A::Error> {
+        let mut properties = HashMap::new();
+        while let Some((name, value)) = try!(map.next_entry::<String, Value>()) {
+            properties.insert(name, PropertyValue::infer(value));
+        }
+        Ok(Properties(properties))
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Layer {
-    pub data: Option<Vec<u8>>,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the layer from the map's origin, in pixels.
+    pub offsetx: f64,
+    pub offsety: f64,
+    /// How fast the layer scrolls relative to the camera;
+    /// 1.0 moves with the map, 0.0 stays fixed on screen.
+    pub parallaxx: f64,
+    pub parallaxy: f64,
+    pub opacity: f64,
+    pub visible: bool,
+    /// Color multiplied with the layer's images when drawn.
+    pub tintcolor: Option<Color>,
+    pub kind: LayerKind,
+    pub properties: Properties,
+}
+
+/// The contents of a `Layer`, which depend on the layer's type.
+#[derive(Clone, Debug)]
+pub enum LayerKind {
+    /// A `tilelayer`, holding one GID per tile. The top bits of
+    /// each GID may hold `FLIPPED_*` flags.
+    Tiles(Vec<u32>),
+    /// A `tilelayer` of an infinite map, split into chunks.
+    Chunks(Vec<Chunk>),
+    /// An `objectgroup`, holding free-standing objects such as
+    /// spawn points, triggers and collision shapes.
+    Objects(Vec<Object>),
+    /// An `imagelayer`, showing a single image.
+    Image(ImageLayer),
+    /// A `group` of layers, whose offset, opacity and visibility
+    /// apply to all of its children. See `Map::flattened_layers`.
+    Group(Vec<Layer>),
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageLayer {
+    /// Path of the image, relative to the map file.
+    pub image: String,
+    /// Whether the image repeats along each axis.
+    pub repeatx: bool,
+    pub repeaty: bool,
+}
+
+/// A rectangular piece of an infinite map's tile layer.
+#[derive(Serialize, Clone, Debug)]
+pub struct Chunk {
+    /// Position of the chunk's top-left tile, in tiles. May be negative.
+    pub x: i32,
+    pub y: i32,
     pub width: u32,
     pub height: u32,
+    pub data: Vec<u32>,
+}
+
+/// A layer as written in JSON, where the fields that make up
+/// its `LayerKind` depend on `type`.
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type", default = "default_layer_type")]
+    layer_type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(default)]
+    offsetx: f64,
+    #[serde(default)]
+    offsety: f64,
+    #[serde(default = "default_one")]
+    parallaxx: f64,
+    #[serde(default = "default_one")]
+    parallaxy: f64,
+    #[serde(default = "default_one")]
+    opacity: f64,
+    #[serde(default = "default_true")]
+    visible: bool,
+    tintcolor: Option<Color>,
+    #[serde(default)]
+    properties: Properties,
+    // tilelayer
+    compression: Option<String>,
+    data: Option<JsonData>,
+    chunks: Option<Vec<JsonChunk>>,
+    // objectgroup
+    #[serde(default)]
+    objects: Vec<Object>,
+    // imagelayer
+    image: Option<String>,
+    #[serde(default)]
+    repeatx: bool,
+    #[serde(default)]
+    repeaty: bool,
+    // group
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+struct JsonChunk {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    data: JsonData,
+}
+
+/// The `data` of a tile layer or chunk: an array of GIDs or, when
+/// the layer's `encoding` is `"base64"`, a (possibly compressed)
+/// string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonData {
+    Gids(Vec<u32>),
+    Base64(String),
+}
+
+impl JsonData {
+    fn into_gids(self, compression: Option<&str>) -> Result<Vec<u32>, String> {
+        match self {
+            JsonData::Gids(gids) => Ok(gids),
+            JsonData::Base64(s) => decode_base64_data(&s, compression).map_err(|e| format!("{:?}", e)),
+        }
+    }
+}
+
+impl JsonLayer {
+    fn into_layer(self) -> Result<Layer, String> {
+        let kind = match &self.layer_type[..] {
+            "tilelayer" => {
+                let compression = self.compression.as_ref().map(|c| &c[..]);
+                // infinite maps store their tiles as chunks
+                match (self.chunks, self.data) {
+                    (Some(chunks), _) => {
+                        let mut out = Vec::with_capacity(chunks.len());
+                        for c in chunks {
+                            out.push(Chunk {
+                                x: c.x,
+                                y: c.y,
+                                width: c.width,
+                                height: c.height,
+                                data: try!(c.data.into_gids(compression)),
+                            });
+                        }
+                        LayerKind::Chunks(out)
+                    },
+                    (None, Some(data)) => LayerKind::Tiles(try!(data.into_gids(compression))),
+                    (None, None) => return Err(format!("tile layer `{}` has no data", self.name)),
+                }
+            },
+            "objectgroup" => LayerKind::Objects(self.objects),
+            "imagelayer" => LayerKind::Image(ImageLayer {
+                image: try!(self.image.ok_or(format!("image layer `{}` has no image", self.name))),
+                repeatx: self.repeatx,
+                repeaty: self.repeaty,
+            }),
+            "group" => LayerKind::Group(self.layers),
+            t => return Err(format!("unsupported layer type `{}`", t)),
+        };
+
+        Ok(Layer {
+            name: self.name,
+            width: self.width,
+            height: self.height,
+            offsetx: self.offsetx,
+            offsety: self.offsety,
+            parallaxx: self.parallaxx,
+            parallaxy: self.parallaxy,
+            opacity: self.opacity,
+            visible: self.visible,
+            tintcolor: self.tintcolor,
+            kind: kind,
+            properties: self.properties,
+        })
+    }
+}
+
+/// `Layer` is decoded through `JsonLayer` because its contents depend
+/// on `type`, and because tile data may be encoded, compressed or
+/// split into chunks.
+impl<'de> Deserialize<'de> for Layer {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let layer = try!(JsonLayer::deserialize(d));
+        layer.into_layer().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Layer {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut m = try!(s.serialize_map(None));
+        let layer_type = match self.kind {
+            LayerKind::Tiles(_) | LayerKind::Chunks(_) => "tilelayer",
+            LayerKind::Objects(_) => "objectgroup",
+            LayerKind::Image(_) => "imagelayer",
+            LayerKind::Group(_) => "group",
+        };
+        try!(m.serialize_entry("type", layer_type));
+        try!(m.serialize_entry("name", &self.name));
+        try!(m.serialize_entry("x", &0));
+        try!(m.serialize_entry("y", &0));
+        try!(m.serialize_entry("width", &self.width));
+        try!(m.serialize_entry("height", &self.height));
+        try!(m.serialize_entry("offsetx", &self.offsetx));
+        try!(m.serialize_entry("offsety", &self.offsety));
+        try!(m.serialize_entry("parallaxx", &self.parallaxx));
+        try!(m.serialize_entry("parallaxy", &self.parallaxy));
+        try!(m.serialize_entry("opacity", &self.opacity));
+        try!(m.serialize_entry("visible", &self.visible));
+        if let Some(ref tint) = self.tintcolor {
+            try!(m.serialize_entry("tintcolor", tint));
+        }
+        if !self.properties.is_empty() {
+            try!(m.serialize_entry("properties", &self.properties));
+        }
+        match self.kind {
+            LayerKind::Tiles(ref data) => try!(m.serialize_entry("data", data)),
+            LayerKind::Chunks(ref chunks) => try!(m.serialize_entry("chunks", chunks)),
+            LayerKind::Objects(ref objects) => {
+                try!(m.serialize_entry("draworder", "topdown"));
+                try!(m.serialize_entry("objects", objects));
+            },
+            LayerKind::Image(ref il) => {
+                try!(m.serialize_entry("image", &il.image));
+                try!(m.serialize_entry("repeatx", &il.repeatx));
+                try!(m.serialize_entry("repeaty", &il.repeaty));
+            },
+            LayerKind::Group(ref layers) => try!(m.serialize_entry("layers", layers)),
+        }
+        m.end()
+    }
+}
+
+/// An object placed in an object layer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Object {
+    #[serde(default)]
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    /// The object's `type` field (a keyword in Rust, hence the name),
+    /// called `class` since Tiled 1.9.
+    #[serde(rename = "type", alias = "class", default)]
+    pub object_type: String,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+    /// Clockwise rotation in degrees.
+    #[serde(default)]
+    pub rotation: f64,
+    #[serde(default = "default_true")]
+    pub visible: bool,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    #[serde(flatten)]
+    pub shape: Shape,
+    /// The text shown by a text object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<Text>,
+    /// Path of the template the object is an instance of, relative
+    /// to the map file. The template's fields have already been
+    /// merged into the object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+/// The text of a text object, drawn inside the object's rectangle.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Text {
+    pub text: String,
+    #[serde(default = "default_font_family")]
+    pub fontfamily: String,
+    /// Font size, in pixels.
+    #[serde(default = "default_pixel_size")]
+    pub pixelsize: u32,
+    /// Whether lines wrap at the object's width.
+    #[serde(default)]
+    pub wrap: bool,
+    #[serde(default = "default_color")]
+    pub color: Color,
+    #[serde(default)]
+    pub bold: bool,
+    #[serde(default)]
+    pub italic: bool,
+    #[serde(default)]
+    pub underline: bool,
+    #[serde(default)]
+    pub strikeout: bool,
+    #[serde(default = "default_true")]
+    pub kerning: bool,
+    /// `left`, `center`, `right` or `justify`.
+    #[serde(default = "default_halign")]
+    pub halign: String,
+    /// `top`, `center` or `bottom`.
+    #[serde(default = "default_valign")]
+    pub valign: String,
+}
+
+impl Text {
+    /// Build a `Text` from a TMX `<text>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        let flag = |name: &str, default: bool| el.attr_or(name, default as u8).map(|v| v != 0);
+        Ok(Text {
+            text: el.text.clone(),
+            fontfamily: try!(el.attr_or("fontfamily", default_font_family())),
+            pixelsize: try!(el.attr_or("pixelsize", default_pixel_size())),
+            wrap: try!(flag("wrap", false)),
+            color: match el.attributes.get("color") {
+                Some(c) => try!(Color::parse(c).ok_or(ReadError::ParseError(
+                    format!("invalid text color {:?}", c)))),
+                None => default_color(),
+            },
+            bold: try!(flag("bold", false)),
+            italic: try!(flag("italic", false)),
+            underline: try!(flag("underline", false)),
+            strikeout: try!(flag("strikeout", false)),
+            kerning: try!(flag("kerning", true)),
+            halign: try!(el.attr_or("halign", default_halign())),
+            valign: try!(el.attr_or("valign", default_valign())),
+        })
+    }
+}
+
+/// The shape of an `Object`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// The object's `width` x `height` rectangle. Tile and
+    /// text objects are rectangles too.
+    Rectangle,
+    /// The ellipse that fits the object's rectangle.
+    Ellipse,
+    /// A single point at the object's position.
+    Point,
+    /// A closed polygon, such as an irregular collision zone.
+    Polygon(Vec<Vertex>),
+    /// An open path, such as a patrol route.
+    Polyline(Vec<Vertex>),
+}
+
+/// The fields of a JSON object that make up its `Shape`.
+#[derive(Deserialize)]
+struct JsonShape {
+    #[serde(default)]
+    ellipse: bool,
+    #[serde(default)]
+    point: bool,
+    polygon: Option<Vec<Vertex>>,
+    polyline: Option<Vec<Vertex>>,
 }
 
-#[derive(RustcDecodable, RustcEncodable, Clone, Debug)]
+impl<'de> Deserialize<'de> for Shape {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = try!(JsonShape::deserialize(d));
+        Ok(match (s.polygon, s.polyline) {
+            (Some(vs), _) => Shape::Polygon(vs),
+            (None, Some(vs)) => Shape::Polyline(vs),
+            _ if s.ellipse => Shape::Ellipse,
+            _ if s.point => Shape::Point,
+            _ => Shape::Rectangle,
+        })
+    }
+}
+
+impl Serialize for Shape {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut m = try!(s.serialize_map(None));
+        match *self {
+            Shape::Rectangle => (),
+            Shape::Ellipse => try!(m.serialize_entry("ellipse", &true)),
+            Shape::Point => try!(m.serialize_entry("point", &true)),
+            Shape::Polygon(ref vs) => try!(m.serialize_entry("polygon", vs)),
+            Shape::Polyline(ref vs) => try!(m.serialize_entry("polyline", vs)),
+        }
+        m.end()
+    }
+}
+
+impl Shape {
+    /// Build a `Shape` from the children of a TMX `<object>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        if let Some(p) = el.child("polygon") {
+            return Ok(Shape::Polygon(try!(Vertex::parse_points(&try!(p.attr::<String>("points"))))));
+        }
+        if let Some(p) = el.child("polyline") {
+            return Ok(Shape::Polyline(try!(Vertex::parse_points(&try!(p.attr::<String>("points"))))));
+        }
+        if el.child("ellipse").is_some() {
+            return Ok(Shape::Ellipse);
+        }
+        if el.child("point").is_some() {
+            return Ok(Shape::Point);
+        }
+        Ok(Shape::Rectangle)
+    }
+}
+
+/// A vertex of a polygon, relative to its object's position.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Vertex {
+    /// Parse TMX `points`, e.g. `"0,0 16,0 16,16"`.
+    fn parse_points(points: &str) -> Result<Vec<Vertex>, ReadError> {
+        points.split_whitespace().map(|p| {
+            let mut xy = p.split(',').map(|v| v.parse());
+            match (xy.next(), xy.next(), xy.next()) {
+                (Some(Ok(x)), Some(Ok(y)), None) => Ok(Vertex { x: x, y: y }),
+                _ => Err(ReadError::ParseError(format!("invalid point {:?}", p))),
+            }
+        }).collect()
+    }
+}
+
+impl Object {
+    /// Returns the object's vertices in the coordinates of its layer,
+    /// rotated about its position: the vertices of a polygon or
+    /// polyline, the corners of a rectangle or of an ellipse's
+    /// bounding box, or the position of a point.
+    pub fn vertices(&self) -> Vec<Vertex> {
+        let corners = vec!(
+            Vertex { x: 0.0, y: 0.0 },
+            Vertex { x: self.width, y: 0.0 },
+            Vertex { x: self.width, y: self.height },
+            Vertex { x: 0.0, y: self.height },
+        );
+        let local = match self.shape {
+            Shape::Rectangle | Shape::Ellipse => corners,
+            Shape::Point => vec!(Vertex { x: 0.0, y: 0.0 }),
+            Shape::Polygon(ref vs) | Shape::Polyline(ref vs) => vs.clone(),
+        };
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        local.iter().map(|v| Vertex {
+            x: self.x + v.x*cos - v.y*sin,
+            y: self.y + v.x*sin + v.y*cos,
+        }).collect()
+    }
+
+    /// Build an `Object` from a TMX `<object>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        Ok(Object {
+            id: try!(el.attr_or("id", 0)),
+            name: try!(el.attr_or("name", String::new())),
+            object_type: try!(el.attr_or("type", try!(el.attr_or("class", String::new())))),
+            x: try!(el.attr("x")),
+            y: try!(el.attr("y")),
+            width: try!(el.attr_or("width", 0.0)),
+            height: try!(el.attr_or("height", 0.0)),
+            rotation: try!(el.attr_or("rotation", 0.0)),
+            // TMX writes visibility as 0/1
+            visible: try!(el.attr_or("visible", 1u8)) != 0,
+            properties: try!(Properties::from_tmx(el)),
+            shape: try!(Shape::from_tmx(el)),
+            text: match el.child("text") {
+                Some(t) => Some(try!(Text::from_tmx(t))),
+                None => None,
+            },
+            template: el.attributes.get("template").cloned(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Map {
     pub layers: Vec<Layer>,
     pub width: u32,
@@ -60,16 +1369,695 @@ pub struct Map {
     pub tilesets: Vec<Tileset>,
     pub tilewidth: u32,
     pub tileheight: u32,
+    #[serde(default, skip_serializing_if = "Properties::is_empty")]
+    pub properties: Properties,
+    #[serde(default)]
+    pub orientation: Orientation,
+    /// Length of a hexagon's flat side, for hexagonal maps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hexsidelength: Option<u32>,
+    /// `x` or `y`: the axis along which staggered and hexagonal
+    /// maps shift every other row or column.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub staggeraxis: Option<String>,
+    /// `odd` or `even`: which rows or columns are shifted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub staggerindex: Option<String>,
+    /// Color the map is cleared to before it's drawn.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backgroundcolor: Option<Color>,
+}
+
+/// Merge the object template (`.tj` file, relative to `dir`) that
+/// each object with a `template` field in the JSON tree `v` refers
+/// to into the object. The object's own fields and properties take
+/// precedence over the template's.
+fn apply_json_templates(v: &mut Value, dir: &Path, cache: &mut HashMap<PathBuf, Value>) -> Result<(), ReadError> {
+    match *v {
+        Value::Array(ref mut a) => for e in a.iter_mut() {
+            try!(apply_json_templates(e, dir, cache));
+        },
+        Value::Object(ref mut obj) => {
+            for e in obj.values_mut() {
+                try!(apply_json_templates(e, dir, cache));
+            }
+            let source = match obj.get("template") {
+                Some(&Value::String(ref t)) => dir.join(t),
+                _ => return Ok(()),
+            };
+            if !cache.contains_key(&source) {
+                let tj: Value = try!(read_json_file(&source));
+                let object = try!(tj.get("object").cloned().ok_or(ReadError::ParseError(
+                    format!("template {:?} has no object", source))));
+                cache.insert(source.clone(), object);
+            }
+
+            let template = match cache[&source] {
+                Value::Object(ref t) => t,
+                _ => return Err(ReadError::ParseError(format!("template {:?} has no object", source))),
+            };
+            for (k, tv) in template {
+                if !obj.contains_key(k) {
+                    obj.insert(k.clone(), tv.clone());
+                    continue;
+                }
+                if k != "properties" {
+                    continue;
+                }
+                if let (Some(&mut Value::Array(ref mut own)), &Value::Array(ref inherited)) = (obj.get_mut(k), tv) {
+                    for p in inherited {
+                        if !own.iter().any(|o| o.get("name") == p.get("name")) {
+                            own.push(p.clone());
+                        }
+                    }
+                }
+            }
+        },
+        _ => (),
+    }
+    Ok(())
+}
+
+/// A `Map` along with the fields Tiled expects in a map
+/// file but that we don't keep.
+#[derive(Serialize)]
+struct JsonMap<'a> {
+    #[serde(rename = "type")]
+    map_type: &'static str,
+    version: &'static str,
+    renderorder: &'static str,
+    infinite: bool,
+    nextobjectid: u32,
+    #[serde(flatten)]
+    map: &'a Map,
+}
+
+/// How a map's tiles are laid out.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Orthogonal,
+    /// Diamond-shaped isometric tiles, with the map's first
+    /// tile at the top.
+    Isometric,
+    /// Isometric tiles laid out in zig-zagging rows or columns.
+    Staggered,
+    Hexagonal,
+}
+
+impl Default for Orientation {
+    fn default() -> Self {
+        Orientation::Orthogonal
+    }
+}
+
+impl Orientation {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "orthogonal" => Some(Orientation::Orthogonal),
+            "isometric" => Some(Orientation::Isometric),
+            "staggered" => Some(Orientation::Staggered),
+            "hexagonal" => Some(Orientation::Hexagonal),
+            _ => None,
+        }
+    }
+}
+
+/// Number of `tile`-sized tiles that fit in an image of size `image`,
+/// laid out with `margin` around the edges and `spacing` between tiles.
+fn tiles_in_image(image: (u32, u32), tile: (u32, u32), margin: u32, spacing: u32) -> u32 {
+    columns_in_image(image.0, tile.0, margin, spacing) * columns_in_image(image.1, tile.1, margin, spacing)
+}
+
+/// Number of `tile`-wide columns that fit in an `image`-wide image,
+/// laid out with `margin` around the edges and `spacing` between
+/// tiles. The last column has no spacing after it. Works the same
+/// for rows, given heights.
+pub fn columns_in_image(image: u32, tile: u32, margin: u32, spacing: u32) -> u32 {
+    if tile + spacing == 0 {
+        return 0;
+    }
+    (image + spacing).saturating_sub(2*margin) / (tile + spacing)
+}
+
+impl Tileset {
+    /// Build a `Tileset` from a TMX `<tileset>` element. External
+    /// tilesets (`source="foo.tsx"`) are loaded relative to `dir`.
+    fn from_tmx(el: &XmlElement, dir: &Path) -> Result<Self, ReadError> {
+        let firstgid = try!(el.attr("firstgid"));
+        if let Some(source) = el.attributes.get("source") {
+            let ts = Tileset { firstgid: firstgid, source: Some(source.clone()), ..Tileset::default() };
+            return ts.load_source(dir);
+        }
+        Tileset::from_tmx_tileset(el, firstgid)
+    }
+
+    /// Load the external tileset, TSX or JSON, that `source` refers
+    /// to, relative to the map's directory `dir`, keeping `firstgid`.
+    /// Tilesets without a `source` are returned as they are.
+    fn load_source(self, dir: &Path) -> Result<Self, ReadError> {
+        let source = match self.source {
+            Some(ref source) => Path::new(source).to_path_buf(),
+            None => return Ok(self),
+        };
+        let path = dir.join(&source);
+        let ts: Tileset = match path.extension().and_then(|e| e.to_str()) {
+            Some("tsx") => {
+                let tsx = try!(XmlElement::open(&path));
+                try!(Tileset::from_tmx_tileset(&tsx, self.firstgid).map_err(|e| e.in_file(&path)))
+            },
+            _ => try!(read_json_file(&path)),
+        };
+        // the image is relative to the tileset file
+        let image = source.parent().unwrap_or(Path::new("")).join(&ts.image);
+        Ok(Tileset {
+            firstgid: self.firstgid,
+            source: None,
+            image: image.to_string_lossy().into_owned(),
+            ..ts
+        })
+    }
+
+    fn from_tmx_tileset(el: &XmlElement, firstgid: u32) -> Result<Self, ReadError> {
+        let image = try!(el.child("image").ok_or(ReadError::ParseError(
+            "<tileset> has no <image>".to_string())));
+        let (tilewidth, tileheight): (u32, u32) = (try!(el.attr("tilewidth")), try!(el.attr("tileheight")));
+        let (margin, spacing) = (try!(el.attr_or("margin", 0)), try!(el.attr_or("spacing", 0)));
+        let (imagewidth, imageheight): (u32, u32) = (try!(image.attr("width")), try!(image.attr("height")));
+
+        // older versions of Tiled don't write `tilecount`
+        let tilecount = if el.attributes.contains_key("tilecount") {
+            try!(el.attr("tilecount"))
+        } else {
+            tiles_in_image((imagewidth, imageheight), (tilewidth, tileheight), margin, spacing)
+        };
+        let columns = try!(el.attr_or("columns", columns_in_image(imagewidth, tilewidth, margin, spacing)));
+
+        let mut tiles = vec!();
+        for t in el.children.iter().filter(|t| t.name == "tile") {
+            tiles.push(try!(Tile::from_tmx(t)));
+        }
+
+        Ok(Tileset {
+            firstgid: firstgid,
+            source: None,
+            image: try!(image.attr("source")),
+            imagewidth: imagewidth,
+            imageheight: imageheight,
+            tileheight: tileheight,
+            tilewidth: tilewidth,
+            tilecount: tilecount,
+            columns: columns,
+            margin: margin,
+            spacing: spacing,
+            tiles: if tiles.is_empty() { None } else { Some(tiles) },
+            properties: try!(Properties::from_tmx(el)),
+            wangsets: match el.child("wangsets") {
+                Some(ws) => Some(try!(ws.children.iter()
+                    .filter(|w| w.name == "wangset")
+                    .map(WangSet::from_tmx)
+                    .collect())),
+                None => None,
+            },
+            terrains: match el.child("terraintypes") {
+                Some(ts) => {
+                    let mut terrains = vec!();
+                    for t in ts.children.iter().filter(|t| t.name == "terrain") {
+                        terrains.push(Terrain {
+                            name: try!(t.attr_or("name", String::new())),
+                            tile: try!(t.attr_or("tile", -1)),
+                        });
+                    }
+                    Some(terrains)
+                },
+                None => None,
+            },
+        })
+    }
+}
+
+impl Layer {
+    /// Whether `name` is the name of a TMX layer element.
+    fn is_tmx_layer(name: &str) -> bool {
+        match name {
+            "layer" | "objectgroup" | "imagelayer" | "group" => true,
+            _ => false,
+        }
+    }
+
+    /// Build a `Layer` from a TMX `<layer>`, `<objectgroup>`,
+    /// `<imagelayer>` or `<group>` element.
+    fn from_tmx(el: &XmlElement) -> Result<Self, ReadError> {
+        let kind = match &el.name[..] {
+            "group" => LayerKind::Group(try!(el.children.iter()
+                .filter(|c| Layer::is_tmx_layer(&c.name))
+                .map(Layer::from_tmx)
+                .collect())),
+            "imagelayer" => {
+                let image = try!(el.child("image").ok_or(ReadError::ParseError(
+                    "<imagelayer> has no <image>".to_string())));
+                LayerKind::Image(ImageLayer {
+                    image: try!(image.attr("source")),
+                    repeatx: try!(el.attr_or("repeatx", 0u8)) != 0,
+                    repeaty: try!(el.attr_or("repeaty", 0u8)) != 0,
+                })
+            },
+            "objectgroup" => LayerKind::Objects(try!(el.children.iter()
+                .filter(|c| c.name == "object")
+                .map(Object::from_tmx)
+                .collect())),
+            _ => match el.child("data") {
+                Some(d) => {
+                    let (encoding, compression) = (d.attributes.get("encoding").map(|e| &e[..]),
+                        d.attributes.get("compression").map(|c| &c[..]));
+                    if d.child("chunk").is_some() {
+                        let mut chunks = vec!();
+                        for c in d.children.iter().filter(|c| c.name == "chunk") {
+                            chunks.push(Chunk {
+                                x: try!(c.attr("x")),
+                                y: try!(c.attr("y")),
+                                width: try!(c.attr("width")),
+                                height: try!(c.attr("height")),
+                                data: try!(Layer::decode_tmx_data(c, encoding, compression)),
+                            });
+                        }
+                        LayerKind::Chunks(chunks)
+                    } else {
+                        LayerKind::Tiles(try!(Layer::decode_tmx_data(d, encoding, compression)))
+                    }
+                },
+                None => return Err(ReadError::ParseError("<layer> has no <data>".to_string())),
+            },
+        };
+
+        Ok(Layer {
+            name: try!(el.attr_or("name", String::new())),
+            width: try!(el.attr_or("width", 0)),
+            height: try!(el.attr_or("height", 0)),
+            offsetx: try!(el.attr_or("offsetx", 0.0)),
+            offsety: try!(el.attr_or("offsety", 0.0)),
+            parallaxx: try!(el.attr_or("parallaxx", 1.0)),
+            parallaxy: try!(el.attr_or("parallaxy", 1.0)),
+            opacity: try!(el.attr_or("opacity", 1.0)),
+            visible: try!(el.attr_or("visible", 1u8)) != 0,
+            tintcolor: match el.attributes.get("tintcolor") {
+                Some(c) => Some(try!(Color::parse(c).ok_or(ReadError::ParseError(
+                    format!("invalid tint color {:?}", c))))),
+                None => None,
+            },
+            kind: kind,
+            properties: try!(Properties::from_tmx(el)),
+        })
+    }
+
+    /// Decode the GIDs held by a `<data>` or `<chunk>` element.
+    fn decode_tmx_data(el: &XmlElement, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>, ReadError> {
+        match encoding {
+            None => el.children.iter()
+                .filter(|c| c.name == "tile")
+                .map(|c| c.attr_or("gid", 0))
+                .collect(),
+            Some("csv") => el.text.split(',')
+                .map(|gid| gid.trim())
+                .filter(|gid| !gid.is_empty())
+                .map(|gid| gid.parse().map_err(|_| ReadError::ParseError(
+                    format!("invalid gid {:?} in layer data", gid))))
+                .collect(),
+            Some("base64") => decode_base64_data(&el.text, compression),
+            Some(e) => Err(ReadError::ParseError(format!("unsupported layer encoding `{}`", e))),
+        }
+    }
 }
 
 impl Map {
+    /// Read a map saved in Tiled's JSON format. The map is decoded
+    /// as the file is read, and fields we don't use are skipped.
+    ///
+    /// Maps with objects created from templates are read a second
+    /// time, as a JSON tree that the templates are merged into.
+    /// External tilesets, TSX or JSON, are loaded relative to the map.
     pub fn read_json<P: AsRef<Path>>(path: P) -> Result<Self, ReadError> {
-        let mut f = try!(File::open(path));
-        let mut contents = vec!();
-        try!(f.read_to_end(&mut contents));
-        let contents = try!(String::from_utf8(contents));
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let mut map: Map = try!(read_json_file(path));
+        if map.uses_templates() {
+            let mut tree: Value = try!(read_json_file(path));
+            try!(apply_json_templates(&mut tree, dir, &mut HashMap::new()));
+            map = try!(serde_json::from_value(tree).map_err(|e| ReadError::from(e).in_file(path)));
+        }
+
+        let mut tilesets = vec!();
+        for ts in map.tilesets.drain(..) {
+            tilesets.push(try!(ts.load_source(dir).map_err(|e| e.in_file(path))));
+        }
+        map.tilesets = tilesets;
+        Ok(map)
+    }
 
-        let map = try!(json::decode(&contents));
+    /// Read a JSON map from `r`, e.g. a network stream. Templates
+    /// and external tilesets can't be found without the map's path,
+    /// so objects created from templates only have the fields set on
+    /// the object itself, and external tilesets keep their `source`.
+    pub fn read_from<R: Read>(r: R) -> Result<Self, ReadError> {
+        let map = try!(serde_json::from_reader(r));
         Ok(map)
     }
+
+    /// Read a JSON map from `bytes`, e.g. a map embedded with
+    /// `include_bytes!`. See `read_from` regarding templates and
+    /// external tilesets.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, ReadError> {
+        let map = try!(serde_json::from_slice(bytes));
+        Ok(map)
+    }
+
+    /// Whether any object in the map was created from a template.
+    fn uses_templates(&self) -> bool {
+        self.flattened_layers().iter().any(|l| match l.kind {
+            LayerKind::Objects(ref objects) => objects.iter().any(|o| o.template.is_some()),
+            _ => false,
+        })
+    }
+
+    /// Write the map in Tiled's JSON format, so that it can be opened
+    /// in Tiled again. Tilesets are embedded in the map.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), WriteError> {
+        let mut w = BufWriter::new(try!(File::create(path)));
+        try!(serde_json::to_writer_pretty(&mut w, &JsonMap {
+            map_type: "map",
+            version: "1.5",
+            renderorder: "right-down",
+            infinite: self.flattened_layers().iter().any(|l| match l.kind {
+                LayerKind::Chunks(_) => true,
+                _ => false,
+            }),
+            nextobjectid: self.flattened_layers().iter()
+                .filter_map(|l| match l.kind {
+                    LayerKind::Objects(ref objects) => objects.iter().map(|o| o.id).max(),
+                    _ => None,
+                })
+                .max().unwrap_or(0) + 1,
+            map: self,
+        }));
+        try!(w.flush());
+        Ok(())
+    }
+
+    /// Read a map in either format, going by the file's extension.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ReadError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tmx") => Map::read_tmx(path),
+            _ => Map::read_json(path),
+        }
+    }
+
+    /// Read a map saved in Tiled's native TMX (XML) format.
+    pub fn read_tmx<P: AsRef<Path>>(path: P) -> Result<Self, ReadError> {
+        let path = path.as_ref();
+        Map::read_tmx_file(path).map_err(|e| e.in_file(path))
+    }
+
+    fn read_tmx_file(path: &Path) -> Result<Self, ReadError> {
+        let mut root = try!(XmlElement::open(path));
+        if root.name != "map" {
+            return Err(ReadError::ParseError(format!("expected <map>, found <{}>", root.name)));
+        }
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        try!(root.apply_templates(dir, &mut HashMap::new()));
+        let (mut layers, mut tilesets) = (vec!(), vec!());
+        for child in &root.children {
+            match &child.name[..] {
+                "tileset" => tilesets.push(try!(Tileset::from_tmx(child, dir))),
+                name if Layer::is_tmx_layer(name) => layers.push(try!(Layer::from_tmx(child))),
+                _ => (),
+            }
+        }
+
+        Ok(Map {
+            layers: layers,
+            width: try!(root.attr("width")),
+            height: try!(root.attr("height")),
+            tilesets: tilesets,
+            tilewidth: try!(root.attr("tilewidth")),
+            tileheight: try!(root.attr("tileheight")),
+            properties: try!(Properties::from_tmx(&root)),
+            orientation: {
+                let o: String = try!(root.attr_or("orientation", "orthogonal".to_string()));
+                try!(Orientation::parse(&o).ok_or(ReadError::ParseError(
+                    format!("unsupported orientation `{}`", o))))
+            },
+            hexsidelength: match root.attributes.get("hexsidelength") {
+                Some(_) => Some(try!(root.attr("hexsidelength"))),
+                None => None,
+            },
+            staggeraxis: root.attributes.get("staggeraxis").cloned(),
+            staggerindex: root.attributes.get("staggerindex").cloned(),
+            backgroundcolor: match root.attributes.get("backgroundcolor") {
+                Some(c) => Some(try!(Color::parse(c).ok_or(ReadError::ParseError(
+                    format!("invalid background color {:?}", c))))),
+                None => None,
+            },
+        })
+    }
+
+    /// Every layer that isn't a group, in drawing order (bottom
+    /// first). Each layer's offset, parallax, opacity, visibility
+    /// and tint have those of its enclosing groups applied.
+    pub fn flattened_layers(&self) -> Vec<Layer> {
+        let mut layers = vec!();
+        Map::flatten(&self.layers, (0.0, 0.0), (1.0, 1.0), 1.0, true, None, &mut layers);
+        layers
+    }
+
+    fn flatten(layers: &[Layer], offset: (f64, f64), parallax: (f64, f64), opacity: f64, visible: bool,
+               tint: Option<Color>, out: &mut Vec<Layer>) {
+        for l in layers {
+            let offset = (offset.0 + l.offsetx, offset.1 + l.offsety);
+            let parallax = (parallax.0 * l.parallaxx, parallax.1 * l.parallaxy);
+            let (opacity, visible) = (opacity * l.opacity, visible && l.visible);
+            let tint = match (tint, l.tintcolor) {
+                (Some(a), Some(b)) => Some(a.multiply(&b)),
+                (a, b) => a.or(b),
+            };
+            match l.kind {
+                LayerKind::Group(ref children) => Map::flatten(children, offset, parallax, opacity, visible, tint, out),
+                _ => out.push(Layer {
+                    offsetx: offset.0,
+                    offsety: offset.1,
+                    parallaxx: parallax.0,
+                    parallaxy: parallax.1,
+                    opacity: opacity,
+                    visible: visible,
+                    tintcolor: tint,
+                    ..l.clone()
+                }),
+            }
+        }
+    }
+}
+
+/// A map of a `World`, as listed in the world file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct WorldMap {
+    /// Path of the map, relative to the world file.
+    #[serde(rename = "fileName")]
+    pub file_name: String,
+    /// Position of the map's top-left corner, in world pixels.
+    pub x: i64,
+    pub y: i64,
+    /// Size of the map, in pixels.
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+}
+
+impl WorldMap {
+    /// Whether the map overlaps the `w` x `h` rectangle at `x`, `y`.
+    pub fn overlaps(&self, x: i64, y: i64, w: i64, h: i64) -> bool {
+        x < self.x + self.width as i64 && self.x < x + w &&
+            y < self.y + self.height as i64 && self.y < y + h
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonWorld {
+    #[serde(default)]
+    maps: Vec<WorldMap>,
+    #[serde(default)]
+    patterns: Vec<Value>,
+}
+
+/// Many maps laid out side by side, as described by a Tiled
+/// `.world` file. Maps are only read when first asked for, so
+/// that the game can stream between them.
+pub struct World {
+    /// The directory of the world file.
+    pub dir: PathBuf,
+    pub maps: Vec<WorldMap>,
+    loaded: HashMap<usize, Map>,
+}
+
+impl World {
+    /// Read a `.world` file. Worlds that place maps by matching
+    /// file names against patterns aren't supported.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ReadError> {
+        let path = path.as_ref();
+        let world: JsonWorld = try!(read_json_file(path));
+        if !world.patterns.is_empty() {
+            return Err(ReadError::ParseError("world file patterns aren't supported".to_string()).in_file(path));
+        }
+        Ok(World {
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            maps: world.maps,
+            loaded: HashMap::new(),
+        })
+    }
+
+    /// Returns the path of map `i`.
+    pub fn path(&self, i: usize) -> PathBuf {
+        self.dir.join(&self.maps[i].file_name)
+    }
+
+    /// Returns map `i`, reading it if it isn't loaded yet.
+    pub fn map(&mut self, i: usize) -> Result<&Map, ReadError> {
+        if !self.loaded.contains_key(&i) {
+            let map = try!(Map::read(self.path(i)));
+            self.loaded.insert(i, map);
+        }
+        Ok(&self.loaded[&i])
+    }
+
+    /// Use `map` as map `i`, e.g. after reading it in the background.
+    pub fn insert(&mut self, i: usize, map: Map) {
+        self.loaded.insert(i, map);
+    }
+
+    pub fn is_loaded(&self, i: usize) -> bool {
+        self.loaded.contains_key(&i)
+    }
+
+    /// Drop map `i` until it's next asked for.
+    pub fn unload(&mut self, i: usize) {
+        self.loaded.remove(&i);
+    }
+
+    /// Returns the first map containing the world pixel `x`, `y`.
+    pub fn map_at(&self, x: i64, y: i64) -> Option<usize> {
+        self.maps.iter().position(|m| m.overlaps(x, y, 1, 1))
+    }
+
+    /// Returns every map overlapping the `w` x `h` rectangle at `x`,
+    /// `y`, e.g. the maps around the camera that should be loaded.
+    pub fn maps_in(&self, x: i64, y: i64, w: i64, h: i64) -> Vec<usize> {
+        (0..self.maps.len()).filter(|&i| self.maps[i].overlaps(x, y, w, h)).collect()
+    }
+}
+
+/// How serious a `Diagnostic` is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// The map loads, but may not look or behave as intended.
+    Warning,
+    /// Loading or drawing the map will fail.
+    Error,
+}
+
+/// The part of a map a `Diagnostic` is about.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    Map,
+    /// The layer with this name.
+    Layer(String),
+    /// The tileset with this `firstgid`.
+    Tileset(u32),
+}
+
+/// A problem found by `Map::validate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, location: Location, message: String) -> Self {
+        Diagnostic {
+            severity: severity,
+            location: location,
+            message: message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.location {
+            Location::Map => write!(f, "{}: map: {}", severity, self.message),
+            Location::Layer(ref name) => write!(f, "{}: layer `{}`: {}", severity, name, self.message),
+            Location::Tileset(firstgid) => write!(f, "{}: tileset {}: {}", severity, firstgid, self.message),
+        }
+    }
+}
+
+impl Map {
+    /// Check that the map is consistent, so that problems are reported
+    /// up front rather than surfacing as a panic while drawing.
+    /// `map_path` is where the map was read from; tileset images are
+    /// looked for relative to it.
+    pub fn validate(&self, map_path: &Path) -> Vec<Diagnostic> {
+        let mut diagnostics = vec!();
+        let dir = map_path.parent().unwrap_or(Path::new(""));
+
+        for ts in &self.tilesets {
+            let here = || Location::Tileset(ts.firstgid);
+            if !dir.join(&ts.image).is_file() {
+                diagnostics.push(Diagnostic::new(Severity::Error, here(),
+                    format!("image {:?} doesn't exist", dir.join(&ts.image))));
+            }
+            let fits = tiles_in_image((ts.imagewidth, ts.imageheight), (ts.tilewidth, ts.tileheight), ts.margin, ts.spacing);
+            if ts.tilecount != fits {
+                diagnostics.push(Diagnostic::new(Severity::Warning, here(),
+                    format!("tilecount is {}, but {} tiles fit in the {}x{} image", ts.tilecount, fits, ts.imagewidth, ts.imageheight)));
+            }
+        }
+
+        for layer in self.flattened_layers() {
+            let data = match layer.kind {
+                LayerKind::Tiles(ref data) => data,
+                _ => continue,
+            };
+            let here = || Location::Layer(layer.name.clone());
+            if layer.width != self.width || layer.height != self.height {
+                diagnostics.push(Diagnostic::new(Severity::Error, here(),
+                    format!("layer is {}x{} tiles, but the map is {}x{}", layer.width, layer.height, self.width, self.height)));
+            }
+            if data.len() != (self.width * self.height) as usize {
+                diagnostics.push(Diagnostic::new(Severity::Error, here(),
+                    format!("layer has {} tiles, but the map has {}", data.len(), self.width * self.height)));
+            }
+
+            let mut unknown = data.iter().map(|&gid| gid & !GID_FLAGS).enumerate()
+                .filter(|&(_, gid)| gid != 0 && !self.has_gid(gid));
+            if let Some((i, gid)) = unknown.next() {
+                let (col, row) = (i as u32 % self.width.max(1), i as u32 / self.width.max(1));
+                diagnostics.push(Diagnostic::new(Severity::Error, here(),
+                    format!("{} tiles have GIDs outside every tileset, the first being {} at ({}, {})",
+                        unknown.count() + 1, gid, col, row)));
+            }
+        }
+        diagnostics
+    }
+
+    /// Whether `gid` refers to a tile of one of the map's tilesets.
+    fn has_gid(&self, gid: u32) -> bool {
+        self.tilesets.iter().any(|ts| gid >= ts.firstgid && gid - ts.firstgid < ts.tilecount)
+    }
 }